@@ -0,0 +1,165 @@
+use std::ops::{Range, RangeInclusive};
+
+use crate::text;
+
+// Block selections: the same display columns on a run of lines, however
+// long the lines are and whatever characters they have there.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    // Corners as (line, display column): where the selection started and
+    // where the cursor is. Columns may be past the end of a line.
+    pub anchor: (usize, usize),
+    pub cursor: (usize, usize),
+}
+
+impl Block {
+    pub fn at(y: usize, col: usize) -> Self {
+        Block {
+            anchor: (y, col),
+            cursor: (y, col),
+        }
+    }
+
+    pub fn rows(&self) -> RangeInclusive<usize> {
+        self.anchor.0.min(self.cursor.0)..=self.anchor.0.max(self.cursor.0)
+    }
+
+    pub fn columns(&self) -> Range<usize> {
+        self.anchor.1.min(self.cursor.1)..self.anchor.1.max(self.cursor.1)
+    }
+}
+
+// Characters of `line` on display columns `columns`. A wide character or a
+// tab cut by an edge is taken whole. For an empty range it's where text
+// typed at that column goes.
+pub fn char_range(line: &str, columns: Range<usize>, tab_width: usize) -> Range<usize> {
+    let mut cells = Vec::new();
+    let mut col = 0;
+    for c in line.chars() {
+        let width = text::char_width(c, col, tab_width);
+        cells.push((col, width));
+        col += width;
+    }
+    // Combining marks have no width and stay with the character before.
+    let end = cells
+        .iter()
+        .position(|&(col, width)| col >= columns.end && width > 0)
+        .unwrap_or(cells.len());
+    if columns.is_empty() {
+        return end..end;
+    }
+    let start = cells
+        .iter()
+        .position(|&(col, width)| col + width > columns.start)
+        .unwrap_or(cells.len());
+    start.min(end)..end
+}
+
+// The display column a character before or after `col`, or a column when
+// there is no character there.
+pub fn step(line: &str, col: usize, forward: bool, tab_width: usize) -> usize {
+    let columns = match forward {
+        true => col..col + 1,
+        false => col.saturating_sub(1)..col,
+    };
+    let chars = char_range(line, columns.clone(), tab_width);
+    match (chars.is_empty(), forward) {
+        (true, true) => columns.end,
+        (true, false) => columns.start,
+        (false, true) => text::display_col(line, chars.end, tab_width),
+        (false, false) => text::display_col(line, chars.start, tab_width),
+    }
+}
+
+// Display width of `line`.
+pub fn width(line: &str, tab_width: usize) -> usize {
+    text::display_col(line, line.chars().count(), tab_width)
+}
+
+// Pads `line` with spaces so that it reaches display column `col`.
+pub fn pad(line: &mut String, col: usize, tab_width: usize) {
+    let width = width(line, tab_width);
+    if width < col {
+        line.push_str(&" ".repeat(col - width));
+    }
+}
+
+// Puts `pieces` on lines `y..`, each at display column `col`, adding lines
+// at the end of the file when there are too few. Pieces with text after
+// them are padded to the width of the widest, so that the text stays in
+// line.
+pub fn paste(lines: &mut Vec<String>, y: usize, col: usize, pieces: &[String], tab_width: usize) {
+    let block_width = pieces
+        .iter()
+        .map(|piece| width(piece, tab_width))
+        .max()
+        .unwrap_or(0);
+    for (i, piece) in pieces.iter().enumerate() {
+        if y + i == lines.len() {
+            lines.push(String::new());
+        }
+        let line = &mut lines[y + i];
+        pad(line, col, tab_width);
+        let at = char_range(line, col..col, tab_width).start;
+        let at = text::byte_index(line, at);
+        let mut piece = piece.clone();
+        if at < line.len() {
+            pad(&mut piece, block_width, tab_width);
+        }
+        line.insert_str(at, &piece);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn corners_give_rows_and_columns_in_either_order() {
+        let block = Block {
+            anchor: (3, 8),
+            cursor: (1, 2),
+        };
+        assert_eq!(block.rows(), 1..=3);
+        assert_eq!(block.columns(), 2..8);
+    }
+
+    #[test]
+    fn characters_cut_by_an_edge_are_taken_whole() {
+        assert_eq!(char_range("abcdef", 1..3, 4), 1..3);
+        assert_eq!(char_range("日本語", 1..3, 4), 0..2);
+        assert_eq!(char_range("a\tb", 2..3, 4), 1..2);
+        assert_eq!(char_range("ab", 4..6, 4), 2..2);
+        assert_eq!(char_range("abc", 1..1, 4), 1..1);
+    }
+
+    #[test]
+    fn combining_marks_stay_with_their_character() {
+        assert_eq!(char_range("e\u{301}x", 0..1, 4), 0..2);
+    }
+
+    #[test]
+    fn steps_go_by_characters_then_by_columns() {
+        assert_eq!(step("日本", 0, true, 4), 2);
+        assert_eq!(step("日本", 2, false, 4), 0);
+        assert_eq!(step("ab", 2, true, 4), 3);
+        assert_eq!(step("ab", 5, false, 4), 4);
+        assert_eq!(step("ab", 0, false, 4), 0);
+    }
+
+    #[test]
+    fn pasted_pieces_stay_in_line() {
+        let mut text = lines(&["abcd", "", "x\ty"]);
+        paste(&mut text, 1, 2, &lines(&["1", "22", "3"]), 4);
+        assert_eq!(text, ["abcd", "  1", "x\t22y", "  3"]);
+
+        let mut text = lines(&["abcd", "abcd"]);
+        paste(&mut text, 0, 1, &lines(&["1", "22"]), 4);
+        assert_eq!(text, ["a1 bcd", "a22bcd"]);
+    }
+}
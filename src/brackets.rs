@@ -0,0 +1,132 @@
+use crate::highlight::{Kind, Results};
+
+// How many lines to look through for the other bracket.
+const MAX_LINES: usize = 10_000;
+
+// The other bracket of a pair and whether it comes after this one.
+fn partner(c: char) -> Option<(char, bool)> {
+    match c {
+        '(' => Some((')', true)),
+        '[' => Some((']', true)),
+        '{' => Some(('}', true)),
+        ')' => Some(('(', false)),
+        ']' => Some(('[', false)),
+        '}' => Some(('{', false)),
+        _ => None,
+    }
+}
+
+// Characters of line `y` with their kinds.
+fn line(lines: &[String], results: &Results, y: usize) -> Vec<(char, Kind)> {
+    let kinds = results.kinds(y, &lines[y]);
+    lines[y].chars().zip(kinds).collect()
+}
+
+// The bracket under the cursor, or the one just before it, and the bracket
+// it pairs with, as (line, char index). Brackets in strings and comments
+// don't count.
+pub fn pair(
+    lines: &[String],
+    results: &Results,
+    y: usize,
+    x: usize,
+) -> Option<[(usize, usize); 2]> {
+    let chars = line(lines, results, y);
+    let is_bracket = |i: usize| {
+        chars
+            .get(i)
+            .is_some_and(|&(c, kind)| kind == Kind::Code && partner(c).is_some())
+    };
+    let at = [Some(x), x.checked_sub(1)]
+        .into_iter()
+        .flatten()
+        .find(|&i| is_bracket(i))?;
+    let bracket = chars[at].0;
+    let (other, forward) = partner(bracket)?;
+
+    let mut depth = 0;
+    let mut visit = |row: usize, chars: &[(char, Kind)], i: usize| {
+        match chars[i] {
+            (c, Kind::Code) if c == bracket => depth += 1,
+            (c, Kind::Code) if c == other => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some([(y, at), (row, i)]);
+                }
+            }
+            _ => {}
+        }
+        None
+    };
+
+    if forward {
+        let last = lines.len().min(y + MAX_LINES);
+        for row in y..last {
+            let chars = if row == y {
+                chars.clone()
+            } else {
+                line(lines, results, row)
+            };
+            let from = if row == y { at } else { 0 };
+            if let Some(found) = (from..chars.len()).find_map(|i| visit(row, &chars, i)) {
+                return Some(found);
+            }
+        }
+    } else {
+        let first = y.saturating_sub(MAX_LINES);
+        for row in (first..=y).rev() {
+            let chars = if row == y {
+                chars.clone()
+            } else {
+                line(lines, results, row)
+            };
+            let to = if row == y { at + 1 } else { chars.len() };
+            if let Some(found) = (0..to).rev().find_map(|i| visit(row, &chars, i)) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntect::highlighting::Style;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn brackets_pair_across_lines() {
+        let text = lines(&["fn f() {", "    g(a[0]);", "}"]);
+        let results = Results::default();
+        assert_eq!(pair(&text, &results, 0, 7), Some([(0, 7), (2, 0)]));
+        assert_eq!(pair(&text, &results, 2, 0), Some([(2, 0), (0, 7)]));
+        assert_eq!(pair(&text, &results, 1, 5), Some([(1, 5), (1, 10)]));
+    }
+
+    #[test]
+    fn the_bracket_before_the_cursor_counts_too() {
+        let text = lines(&["(a)"]);
+        let results = Results::default();
+        assert_eq!(pair(&text, &results, 0, 3), Some([(0, 2), (0, 0)]));
+        assert_eq!(pair(&text, &results, 0, 2), Some([(0, 2), (0, 0)]));
+        assert_eq!(pair(&lines(&["a b"]), &results, 0, 1), None);
+        assert_eq!(pair(&lines(&["(a"]), &results, 0, 0), None);
+    }
+
+    #[test]
+    fn brackets_in_strings_are_skipped() {
+        let text = lines(&["f(\")\")"]);
+        let span = |kind, text: &str| (Style::default(), kind, text.to_string());
+        let results = Results::new(vec![vec![
+            span(Kind::Code, "f("),
+            span(Kind::String, "\")\""),
+            span(Kind::Code, ")"),
+        ]]);
+        assert_eq!(pair(&text, &results, 0, 1), Some([(0, 1), (0, 5)]));
+        assert_eq!(pair(&text, &results, 0, 3), None);
+    }
+}
@@ -0,0 +1,222 @@
+use std::env;
+use std::fmt;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use termion::color::Rgb;
+
+// Every colour kk draws goes through `Fg` and `Bg`, which turn theme colours
+// into what the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    TrueColor = 0,
+    Ansi256 = 1,
+    Ansi16 = 2,
+    None = 3,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(ColorMode::TrueColor as u8);
+
+impl ColorMode {
+    pub fn from_name(name: &str) -> Option<Option<Self>> {
+        Some(match name {
+            "auto" => None,
+            "truecolor" | "24bit" => Some(ColorMode::TrueColor),
+            "256" => Some(ColorMode::Ansi256),
+            "16" => Some(ColorMode::Ansi16),
+            "none" => Some(ColorMode::None),
+            _ => return None,
+        })
+    }
+
+    // NO_COLOR (https://no-color.org) wins over everything, then COLORTERM
+    // and the terminfo entry of $TERM.
+    pub fn detect() -> Self {
+        static DETECTED: OnceLock<ColorMode> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+                return ColorMode::None;
+            }
+            if let Ok(colorterm) = env::var("COLORTERM")
+                && (colorterm == "truecolor" || colorterm == "24bit")
+            {
+                return ColorMode::TrueColor;
+            }
+            let term = env::var("TERM").unwrap_or_default();
+            if term == "dumb" {
+                return ColorMode::None;
+            }
+            let Ok(info) = terminfo::Database::from_env() else {
+                return if term.contains("256color") {
+                    ColorMode::Ansi256
+                } else {
+                    ColorMode::Ansi16
+                };
+            };
+            if info.raw("Tc").is_some() || info.raw("RGB").is_some() {
+                return ColorMode::TrueColor;
+            }
+            match info.get::<terminfo::capability::MaxColors>().map(i32::from) {
+                Some(colors) if colors >= 0x1000000 => ColorMode::TrueColor,
+                Some(colors) if colors >= 256 => ColorMode::Ansi256,
+                Some(colors) if colors >= 8 => ColorMode::Ansi16,
+                Some(_) => ColorMode::None,
+                None => ColorMode::Ansi16,
+            }
+        })
+    }
+}
+
+pub fn set_mode(mode: Option<ColorMode>) {
+    let mode = mode.unwrap_or_else(ColorMode::detect);
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn mode() -> ColorMode {
+    match MODE.load(Ordering::Relaxed) {
+        0 => ColorMode::TrueColor,
+        1 => ColorMode::Ansi256,
+        2 => ColorMode::Ansi16,
+        _ => ColorMode::None,
+    }
+}
+
+// Without colours the UI falls back to reverse video where it needs to
+// stand out.
+pub fn enabled() -> bool {
+    mode() != ColorMode::None
+}
+
+// Stands in for a background colour that has to stand out when colours
+// are off.
+pub fn reverse() -> &'static str {
+    if enabled() { "" } else { "\x1b[7m" }
+}
+
+// The default xterm palette.
+const ANSI16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+fn nearest_16(color: (u8, u8, u8)) -> u8 {
+    (0..16)
+        .min_by_key(|&i| distance(color, ANSI16[i]))
+        .unwrap() as u8
+}
+
+// The 6x6x6 cube (16-231) or the grey ramp (232-255), whichever is closer.
+fn nearest_256((r, g, b): (u8, u8, u8)) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |v: u8| {
+        (0..6)
+            .min_by_key(|&i| (LEVELS[i] as i32 - v as i32).abs())
+            .unwrap()
+    };
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let grey_index = ((average.saturating_sub(8)) / 10).min(23) as u8;
+    let grey_level = 8 + grey_index * 10;
+    let grey = (grey_level, grey_level, grey_level);
+
+    if distance((r, g, b), grey) < distance((r, g, b), cube) {
+        232 + grey_index
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
+
+fn write_color(f: &mut fmt::Formatter, Rgb(r, g, b): Rgb, layer: u8) -> fmt::Result {
+    match mode() {
+        ColorMode::TrueColor => write!(f, "\x1b[{}8;2;{};{};{}m", layer, r, g, b),
+        ColorMode::Ansi256 => write!(f, "\x1b[{}8;5;{}m", layer, nearest_256((r, g, b))),
+        ColorMode::Ansi16 => {
+            // 30-37/40-47 and the bright 90-97/100-107, the only codes the
+            // Linux console understands.
+            let index = nearest_16((r, g, b));
+            let base = if index < 8 { layer * 10 } else { layer * 10 + 60 };
+            write!(f, "\x1b[{}m", base + index % 8)
+        }
+        ColorMode::None => Ok(()),
+    }
+}
+
+// One of the 16 colours of the terminal's own palette.
+pub struct Ansi(pub u8);
+
+impl fmt::Display for Ansi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match mode() {
+            ColorMode::None => Ok(()),
+            _ if self.0 < 8 => write!(f, "\x1b[{}m", 30 + self.0),
+            _ => write!(f, "\x1b[{}m", 90 + self.0 % 8),
+        }
+    }
+}
+
+pub struct Fg(pub Rgb);
+
+impl fmt::Display for Fg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_color(f, self.0, 3)
+    }
+}
+
+pub struct Bg(pub Rgb);
+
+impl fmt::Display for Bg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_color(f, self.0, 4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_are_named_in_the_config() {
+        assert_eq!(ColorMode::from_name("auto"), Some(None));
+        assert_eq!(
+            ColorMode::from_name("24bit"),
+            Some(Some(ColorMode::TrueColor))
+        );
+        assert_eq!(ColorMode::from_name("none"), Some(Some(ColorMode::None)));
+        assert_eq!(ColorMode::from_name("8"), None);
+    }
+
+    #[test]
+    fn colours_map_to_the_nearest_palette_entry() {
+        assert_eq!(nearest_16((250, 10, 10)), 9);
+        assert_eq!(nearest_16((10, 10, 10)), 0);
+        assert_eq!(nearest_256((255, 0, 0)), 196);
+        assert_eq!(nearest_256((0, 0, 0)), 16);
+    }
+
+    #[test]
+    fn greys_use_the_grey_ramp() {
+        assert_eq!(nearest_256((128, 128, 128)), 244);
+        assert_eq!(nearest_256((40, 42, 54)), 235);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::prompt::Completer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    Quit,
+    Save,
+    SaveAndQuit,
+    SaveAs,
+    Search,
+    ShowMessages,
+    DismissMessage,
+    Execute,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    InsertNewline,
+    DeleteBackward,
+    Open,
+    ParentDir,
+    GoTo,
+    ReloadConfig,
+    SelectTheme,
+    SelectSyntax,
+    MatchBracket,
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    Indent,
+    Dedent,
+    Retab,
+    Undo,
+    Redo,
+    ToggleComment,
+    MoveWordLeft,
+    MoveWordRight,
+    DeleteForward,
+    DeleteWordBackward,
+    DeleteWordForward,
+    MoveHome,
+    MoveEnd,
+    PageUp,
+    PageDown,
+    MoveFileStart,
+    MoveFileEnd,
+    SelectHome,
+    SelectEnd,
+    GoToLine,
+    OpenFile,
+    JumpBack,
+    JumpForward,
+    SetMark,
+    JumpToMark,
+    AddCursorAbove,
+    AddCursorBelow,
+    AddNextOccurrence,
+    SelectBlockUp,
+    SelectBlockDown,
+    SelectBlockLeft,
+    SelectBlockRight,
+    Copy,
+    Cut,
+    Paste,
+}
+
+const NAMES: &[(Command, &str)] = &[
+    (Command::Quit, "quit"),
+    (Command::Save, "save"),
+    (Command::SaveAndQuit, "save-and-quit"),
+    (Command::SaveAs, "save-as"),
+    (Command::Search, "search"),
+    (Command::ShowMessages, "show-messages"),
+    (Command::DismissMessage, "dismiss-message"),
+    (Command::Execute, "execute"),
+    (Command::MoveUp, "move-up"),
+    (Command::MoveDown, "move-down"),
+    (Command::MoveLeft, "move-left"),
+    (Command::MoveRight, "move-right"),
+    (Command::InsertNewline, "insert-newline"),
+    (Command::DeleteBackward, "delete-backward"),
+    (Command::Open, "open"),
+    (Command::ParentDir, "parent-dir"),
+    (Command::GoTo, "go-to"),
+    (Command::ReloadConfig, "reload-config"),
+    (Command::SelectTheme, "select-theme"),
+    (Command::SelectSyntax, "select-syntax"),
+    (Command::MatchBracket, "match-bracket"),
+    (Command::SelectUp, "select-up"),
+    (Command::SelectDown, "select-down"),
+    (Command::SelectLeft, "select-left"),
+    (Command::SelectRight, "select-right"),
+    (Command::Indent, "indent"),
+    (Command::Dedent, "dedent"),
+    (Command::Retab, "retab"),
+    (Command::Undo, "undo"),
+    (Command::Redo, "redo"),
+    (Command::ToggleComment, "toggle-comment"),
+    (Command::MoveWordLeft, "move-word-left"),
+    (Command::MoveWordRight, "move-word-right"),
+    (Command::DeleteForward, "delete-forward"),
+    (Command::DeleteWordBackward, "delete-word-backward"),
+    (Command::DeleteWordForward, "delete-word-forward"),
+    (Command::MoveHome, "move-home"),
+    (Command::MoveEnd, "move-end"),
+    (Command::PageUp, "page-up"),
+    (Command::PageDown, "page-down"),
+    (Command::MoveFileStart, "move-file-start"),
+    (Command::MoveFileEnd, "move-file-end"),
+    (Command::SelectHome, "select-home"),
+    (Command::SelectEnd, "select-end"),
+    (Command::GoToLine, "go-to-line"),
+    (Command::OpenFile, "open-file"),
+    (Command::JumpBack, "jump-back"),
+    (Command::JumpForward, "jump-forward"),
+    (Command::SetMark, "set-mark"),
+    (Command::JumpToMark, "jump-to-mark"),
+    (Command::AddCursorAbove, "add-cursor-above"),
+    (Command::AddCursorBelow, "add-cursor-below"),
+    (Command::AddNextOccurrence, "add-next-occurrence"),
+    (Command::SelectBlockUp, "select-block-up"),
+    (Command::SelectBlockDown, "select-block-down"),
+    (Command::SelectBlockLeft, "select-block-left"),
+    (Command::SelectBlockRight, "select-block-right"),
+    (Command::Copy, "copy"),
+    (Command::Cut, "cut"),
+    (Command::Paste, "paste"),
+];
+
+pub const EDITOR: &[Command] = &[
+    Command::Quit,
+    Command::Save,
+    Command::SaveAndQuit,
+    Command::SaveAs,
+    Command::Search,
+    Command::ShowMessages,
+    Command::DismissMessage,
+    Command::Execute,
+    Command::MoveUp,
+    Command::MoveDown,
+    Command::MoveLeft,
+    Command::MoveRight,
+    Command::InsertNewline,
+    Command::DeleteBackward,
+    Command::ReloadConfig,
+    Command::SelectTheme,
+    Command::SelectSyntax,
+    Command::MatchBracket,
+    Command::SelectUp,
+    Command::SelectDown,
+    Command::SelectLeft,
+    Command::SelectRight,
+    Command::Indent,
+    Command::Dedent,
+    Command::Retab,
+    Command::Undo,
+    Command::Redo,
+    Command::ToggleComment,
+    Command::MoveWordLeft,
+    Command::MoveWordRight,
+    Command::DeleteForward,
+    Command::DeleteWordBackward,
+    Command::DeleteWordForward,
+    Command::MoveHome,
+    Command::MoveEnd,
+    Command::PageUp,
+    Command::PageDown,
+    Command::MoveFileStart,
+    Command::MoveFileEnd,
+    Command::SelectHome,
+    Command::SelectEnd,
+    Command::GoToLine,
+    Command::OpenFile,
+    Command::JumpBack,
+    Command::JumpForward,
+    Command::SetMark,
+    Command::JumpToMark,
+    Command::AddCursorAbove,
+    Command::AddCursorBelow,
+    Command::AddNextOccurrence,
+    Command::SelectBlockUp,
+    Command::SelectBlockDown,
+    Command::SelectBlockLeft,
+    Command::SelectBlockRight,
+    Command::Copy,
+    Command::Cut,
+    Command::Paste,
+];
+
+pub const EXPLORER: &[Command] = &[
+    Command::Quit,
+    Command::ShowMessages,
+    Command::Execute,
+    Command::MoveUp,
+    Command::MoveDown,
+    Command::Open,
+    Command::ParentDir,
+    Command::GoTo,
+    Command::ReloadConfig,
+];
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        NAMES.iter().find(|(c, _)| c == self).map(|(_, n)| *n).unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        NAMES.iter().find(|(_, n)| *n == name).map(|(c, _)| *c)
+    }
+}
+
+// Completion for the command prompt, limited to the commands of one context.
+pub fn completer(commands: &'static [Command]) -> Completer {
+    Box::new(move |input| {
+        commands
+            .iter()
+            .map(|c| c.name())
+            .filter(|name| name.starts_with(input))
+            .map(|name| name.to_string())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_command_has_its_own_name() {
+        for command in EDITOR.iter().chain(EXPLORER) {
+            assert_eq!(Command::from_name(command.name()), Some(*command));
+        }
+        assert_eq!(Command::from_name("no-such-command"), None);
+    }
+
+    #[test]
+    fn completion_stays_within_the_context() {
+        let complete = completer(EXPLORER);
+        assert_eq!(complete("qu"), ["quit"]);
+        assert!(complete("save").is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::indent;
+
+// Comment syntax of a language.
+pub struct Tokens {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>,
+}
+
+// Syntax name, line comment and block comment.
+type Entry = (
+    &'static str,
+    Option<&'static str>,
+    Option<(&'static str, &'static str)>,
+);
+
+const C_LIKE: Option<(&str, &str)> = Some(("/*", "*/"));
+const MARKUP: Option<(&str, &str)> = Some(("<!--", "-->"));
+
+// The bundled syntaxes come without their `.tmPreferences`, so their comment
+// tokens are listed here by syntax name.
+const BUILTIN: &[Entry] = &[
+    ("Rust", Some("//"), C_LIKE),
+    ("C", Some("//"), C_LIKE),
+    ("C++", Some("//"), C_LIKE),
+    ("C#", Some("//"), C_LIKE),
+    ("Objective-C", Some("//"), C_LIKE),
+    ("Java", Some("//"), C_LIKE),
+    ("JavaScript", Some("//"), C_LIKE),
+    ("JavaScript (Babel)", Some("//"), C_LIKE),
+    ("TypeScript", Some("//"), C_LIKE),
+    ("TypeScriptReact", Some("//"), C_LIKE),
+    ("Go", Some("//"), C_LIKE),
+    ("Swift", Some("//"), C_LIKE),
+    ("Kotlin", Some("//"), C_LIKE),
+    ("Scala", Some("//"), C_LIKE),
+    ("Dart", Some("//"), C_LIKE),
+    ("Zig", Some("//"), None),
+    ("Odin", Some("//"), C_LIKE),
+    ("D", Some("//"), C_LIKE),
+    ("Groovy", Some("//"), C_LIKE),
+    ("Solidity", Some("//"), C_LIKE),
+    ("GLSL", Some("//"), C_LIKE),
+    ("WGSL", Some("//"), C_LIKE),
+    ("Typst", Some("//"), C_LIKE),
+    ("F#", Some("//"), Some(("(*", "*)"))),
+    ("PHP", Some("//"), C_LIKE),
+    ("Protocol Buffer", Some("//"), C_LIKE),
+    ("GraphQL", Some("#"), None),
+    ("CSS", None, C_LIKE),
+    ("SCSS", Some("//"), C_LIKE),
+    ("Less", Some("//"), C_LIKE),
+    ("Python", Some("#"), None),
+    ("Ruby", Some("#"), None),
+    ("Perl", Some("#"), None),
+    ("Bourne Again Shell (bash)", Some("#"), None),
+    ("Fish", Some("#"), None),
+    ("PowerShell", Some("#"), Some(("<#", "#>"))),
+    ("Makefile", Some("#"), None),
+    ("Dockerfile", Some("#"), None),
+    ("Dockerfile (with bash)", Some("#"), None),
+    ("Terraform", Some("#"), C_LIKE),
+    ("Crystal", Some("#"), None),
+    ("CoffeeScript", Some("#"), None),
+    ("AWK", Some("#"), None),
+    ("Tcl", Some("#"), None),
+    ("Puppet", Some("#"), None),
+    ("nginx", Some("#"), None),
+    ("Apache Conf", Some("#"), None),
+    ("DotENV", Some("#"), None),
+    ("Crontab", Some("#"), None),
+    ("Java Properties", Some("#"), None),
+    ("CMake", Some("#"), None),
+    ("YAML", Some("#"), None),
+    ("TOML", Some("#"), None),
+    ("Nix", Some("#"), C_LIKE),
+    ("R", Some("#"), None),
+    ("Elixir", Some("#"), None),
+    ("Julia", Some("#"), None),
+    ("Nim", Some("#"), None),
+    ("Git Config", Some("#"), None),
+    ("Git Ignore", Some("#"), None),
+    ("SSH Config", Some("#"), None),
+    ("Lua", Some("--"), Some(("--[[", "]]"))),
+    ("SQL", Some("--"), C_LIKE),
+    ("Haskell", Some("--"), Some(("{-", "-}"))),
+    ("Elm", Some("--"), Some(("{-", "-}"))),
+    ("VHDL", Some("--"), None),
+    ("Racket", Some(";"), None),
+    ("Jinja2", None, Some(("{#", "#}"))),
+    ("Lisp", Some(";"), None),
+    ("Clojure", Some(";"), None),
+    ("Erlang", Some("%"), None),
+    ("TeX", Some("%"), None),
+    ("LaTeX", Some("%"), None),
+    ("INI", Some(";"), None),
+    ("VimL", Some("\""), None),
+    ("x86_64 Assembly", Some(";"), None),
+    ("OCaml", None, Some(("(*", "*)"))),
+    ("HTML", None, MARKUP),
+    ("XML", None, MARKUP),
+    ("Markdown", None, MARKUP),
+    ("Vue Component", None, MARKUP),
+    ("Svelte", None, MARKUP),
+];
+
+pub fn builtin(syntax: &str) -> Option<Tokens> {
+    let (_, line, block) = BUILTIN.iter().find(|(name, _, _)| *name == syntax)?;
+    Some(Tokens {
+        line: line.map(str::to_string),
+        block: block.map(|(start, end)| (start.to_string(), end.to_string())),
+    })
+}
+
+// `lines` commented out, or uncommented when they all are already. Blank
+// lines are left alone.
+pub fn toggle(lines: &[String], tokens: &Tokens) -> Option<Vec<String>> {
+    match (&tokens.line, &tokens.block) {
+        (Some(token), _) => Some(toggle_lines(lines, token)),
+        (None, Some((start, end))) => Some(toggle_block(lines, start, end)),
+        (None, None) => None,
+    }
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn toggle_lines(lines: &[String], token: &str) -> Vec<String> {
+    let commented = |line: &String| line.trim_start().starts_with(token);
+    if lines.iter().filter(|line| !is_blank(line)).all(commented) {
+        return lines
+            .iter()
+            .map(|line| {
+                let indentation = indent::leading(line);
+                let Some(rest) = line[indentation.len()..].strip_prefix(token) else {
+                    return line.clone();
+                };
+                let rest = rest.strip_prefix(' ').unwrap_or(rest);
+                format!("{}{}", indentation, rest)
+            })
+            .collect();
+    }
+    // The tokens line up at the shallowest indentation.
+    let column = lines
+        .iter()
+        .filter(|line| !is_blank(line))
+        .map(|line| indent::leading(line).len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| match is_blank(line) {
+            true => line.clone(),
+            false => format!("{}{} {}", &line[..column], token, &line[column..]),
+        })
+        .collect()
+}
+
+fn toggle_block(lines: &[String], start: &str, end: &str) -> Vec<String> {
+    let mut lines = lines.to_vec();
+    let text: Vec<usize> = (0..lines.len()).filter(|&i| !is_blank(&lines[i])).collect();
+    let (Some(&first), Some(&last)) = (text.first(), text.last()) else {
+        return lines;
+    };
+    let opened = lines[first].trim_start().starts_with(start);
+    let closed = lines[last].trim_end().ends_with(end);
+    // On one line the tokens mustn't overlap, as in `/*/`.
+    let fits = first != last || lines[first].trim().len() >= start.len() + end.len();
+    if opened && closed && fits {
+        let indentation = indent::leading(&lines[first]).len();
+        let rest = &lines[first][indentation + start.len()..];
+        lines[first] = format!(
+            "{}{}",
+            &lines[first][..indentation],
+            rest.strip_prefix(' ').unwrap_or(rest)
+        );
+        let body = lines[last].trim_end();
+        let body = &body[..body.len() - end.len()];
+        lines[last] = body.strip_suffix(' ').unwrap_or(body).to_string();
+    } else {
+        let indentation = indent::leading(&lines[first]).len();
+        lines[first].insert_str(indentation, &format!("{} ", start));
+        lines[last].push_str(&format!(" {}", end));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn block_tokens_that_overlap_are_not_a_comment() {
+        let toggled = toggle_block(&lines(&["/*/"]), "/*", "*/");
+        assert_eq!(toggled, lines(&["/* /*/ */"]));
+    }
+
+    fn rust() -> Tokens {
+        builtin("Rust").unwrap()
+    }
+
+    #[test]
+    fn line_comments_line_up_at_the_shallowest_indentation() {
+        let text = lines(&["fn f() {", "    x();", "", "}"]);
+        let commented = toggle(&text, &rust()).unwrap();
+        assert_eq!(
+            commented,
+            lines(&["// fn f() {", "//     x();", "", "// }"])
+        );
+        assert_eq!(toggle(&commented, &rust()).unwrap(), text);
+    }
+
+    #[test]
+    fn mixed_lines_are_all_commented() {
+        let text = lines(&["    // a", "    b"]);
+        let commented = toggle(&text, &rust()).unwrap();
+        assert_eq!(commented, lines(&["    // // a", "    // b"]));
+    }
+
+    #[test]
+    fn tokens_without_a_space_are_removed_too() {
+        let text = lines(&["  //a", "  // b"]);
+        assert_eq!(toggle(&text, &rust()).unwrap(), lines(&["  a", "  b"]));
+    }
+
+    #[test]
+    fn block_comments_wrap_the_text() {
+        let css = builtin("CSS").unwrap();
+        let text = lines(&["", "  a {", "  }", ""]);
+        let commented = toggle(&text, &css).unwrap();
+        assert_eq!(commented, lines(&["", "  /* a {", "  } */", ""]));
+        assert_eq!(toggle(&commented, &css).unwrap(), text);
+    }
+
+    #[test]
+    fn unknown_syntaxes_have_no_tokens() {
+        assert!(builtin("Plain Text").is_none());
+        let none = Tokens {
+            line: None,
+            block: None,
+        };
+        assert_eq!(toggle(&lines(&["a"]), &none), None);
+    }
+}
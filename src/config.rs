@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::colors::ColorMode;
+use crate::commands::{self, Command};
+use crate::highlight::BackendKind;
+use crate::keymap::{self, Keymap};
+use crate::pairs;
+use crate::statusline::{self, StatusLine};
+use crate::theme;
+use crate::treesitter::TreeSitter;
+
+// One configuration layer: the global file, a project `.kk.toml` or the
+// `--set` options from the command line. Unset values fall through to the
+// layer below.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Layer {
+    tab_width: Option<usize>,
+    indent_size: Option<usize>,
+    expand_tabs: Option<bool>,
+    auto_indent: Option<bool>,
+    line_numbers: Option<bool>,
+    theme: Option<String>,
+    background: Option<String>,
+    mouse_scroll: Option<usize>,
+    statusline: Option<String>,
+    message_timeout: Option<u64>,
+    colors: Option<String>,
+    highlighter: HashMap<String, String>,
+    pairs: HashMap<String, String>,
+    keys: KeysLayer,
+    // Unknown keys are reported without throwing away the rest of the file.
+    #[serde(flatten)]
+    unknown: HashMap<String, toml::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct KeysLayer {
+    editor: HashMap<String, String>,
+    explorer: HashMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    pub tab_width: usize,
+    pub indent_size: usize,
+    pub expand_tabs: bool,
+    pub auto_indent: bool,
+    pub line_numbers: bool,
+    pub theme: String,
+    // Overrides the theme background.
+    pub background: Option<(u8, u8, u8)>,
+    pub mouse_scroll: usize,
+    pub statusline: String,
+    pub message_timeout: u64,
+    // None detects what the terminal supports.
+    pub colors: Option<ColorMode>,
+    // Highlighting backend per lower-case syntax name.
+    pub highlighter: HashMap<String, BackendKind>,
+    // Auto-paired characters per lower-case syntax name or `default`.
+    pub pairs: HashMap<String, String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            tab_width: 4,
+            indent_size: 4,
+            expand_tabs: false,
+            auto_indent: true,
+            line_numbers: true,
+            theme: theme::DEFAULT_THEME.to_string(),
+            background: None,
+            mouse_scroll: 3,
+            statusline: statusline::DEFAULT_SPEC.to_string(),
+            message_timeout: 4,
+            colors: None,
+            highlighter: HashMap::new(),
+            pairs: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub settings: Settings,
+    pub editor_keys: Keymap,
+    pub explorer_keys: Keymap,
+    pub errors: Vec<String>,
+    overrides: Vec<String>,
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("kk")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/kk")),
+    }
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("kk")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache/kk")),
+    }
+}
+
+pub fn state_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("kk")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state/kk")),
+    }
+}
+
+// The closest `.kk.toml` in `dir` or one of its parents.
+fn project_file(dir: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().unwrap_or(dir.to_path_buf());
+    dir.ancestors()
+        .map(|dir| dir.join(".kk.toml"))
+        .find(|path| path.is_file())
+}
+
+impl Config {
+    // The settings and key bindings kk has without any configuration file.
+    pub fn builtin() -> Self {
+        Config {
+            settings: Settings::default(),
+            editor_keys: Keymap::editor(),
+            explorer_keys: Keymap::explorer(),
+            errors: Vec::new(),
+            overrides: Vec::new(),
+        }
+    }
+
+    // `path` is the file or directory being opened, it decides which
+    // project file applies. `overrides` are `key=value` pairs from `--set`.
+    pub fn load(path: &Path, overrides: &[String]) -> Self {
+        let mut config = Config {
+            overrides: overrides.to_vec(),
+            ..Config::builtin()
+        };
+
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(Path::new("."))
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+
+        let files = [
+            config_dir().map(|dir| dir.join("config.toml")),
+            project_file(dir),
+        ];
+        for file in files.into_iter().flatten() {
+            let Ok(text) = fs::read_to_string(&file) else {
+                continue;
+            };
+            let source = file.display().to_string();
+            match toml::from_str::<Layer>(&text) {
+                Ok(layer) => config.apply(layer, &source),
+                Err(err) => config.errors.push(format!("{}: {}", source, err.message())),
+            }
+        }
+
+        if !overrides.is_empty() {
+            let text = overrides
+                .iter()
+                .map(|pair| override_line(pair))
+                .collect::<Vec<_>>()
+                .join("\n");
+            match toml::from_str::<Layer>(&text) {
+                Ok(layer) => config.apply(layer, "--set"),
+                Err(err) => config.errors.push(format!("--set: {}", err.message())),
+            }
+        }
+        config
+    }
+
+    pub fn reload(&self, path: &Path) -> Self {
+        Config::load(path, &self.overrides)
+    }
+
+    fn apply(&mut self, layer: Layer, source: &str) {
+        let mut unknown: Vec<_> = layer.unknown.keys().collect();
+        unknown.sort();
+        for key in unknown {
+            self.errors.push(format!("{}: unknown setting `{}`", source, key));
+        }
+
+        let settings = &mut self.settings;
+        let mut invalid = |key: &str, reason: &str| {
+            self.errors.push(format!("{}: `{}` {}", source, key, reason));
+        };
+
+        match layer.tab_width {
+            Some(width @ 1..=16) => settings.tab_width = width,
+            Some(_) => invalid("tab_width", "must be between 1 and 16"),
+            None => {}
+        }
+        match layer.indent_size {
+            Some(size @ 1..=16) => settings.indent_size = size,
+            Some(_) => invalid("indent_size", "must be between 1 and 16"),
+            None => {}
+        }
+        if let Some(expand_tabs) = layer.expand_tabs {
+            settings.expand_tabs = expand_tabs;
+        }
+        if let Some(auto_indent) = layer.auto_indent {
+            settings.auto_indent = auto_indent;
+        }
+        if let Some(line_numbers) = layer.line_numbers {
+            settings.line_numbers = line_numbers;
+        }
+        if let Some(name) = layer.theme {
+            match theme::load(&name) {
+                Ok(_) => settings.theme = name,
+                Err(err) => invalid("theme", &err),
+            }
+        }
+        match layer.background.as_deref().map(parse_color) {
+            Some(Some(color)) => settings.background = Some(color),
+            Some(None) => invalid("background", "must be a colour like \"#282a36\""),
+            None => {}
+        }
+        match layer.mouse_scroll {
+            Some(lines @ 1..=100) => settings.mouse_scroll = lines,
+            Some(_) => invalid("mouse_scroll", "must be between 1 and 100"),
+            None => {}
+        }
+        match layer.statusline.as_deref().map(StatusLine::parse) {
+            Some(Ok(_)) => settings.statusline = layer.statusline.unwrap(),
+            Some(Err(err)) => invalid("statusline", &err),
+            None => {}
+        }
+        if let Some(timeout) = layer.message_timeout {
+            settings.message_timeout = timeout;
+        }
+        match layer.colors.as_deref().map(ColorMode::from_name) {
+            Some(Some(mode)) => settings.colors = mode,
+            Some(None) => invalid("colors", "must be one of auto, truecolor, 256, 16, none"),
+            None => {}
+        }
+        let mut highlighter: Vec<_> = layer.highlighter.into_iter().collect();
+        highlighter.sort();
+        for (syntax, name) in highlighter {
+            let key = format!("highlighter.{}", syntax);
+            let syntax = syntax.to_lowercase();
+            match BackendKind::from_name(&name) {
+                Some(BackendKind::TreeSitter) if !TreeSitter::languages().any(|l| l == syntax) => {
+                    invalid(&key, "has no tree-sitter grammar")
+                }
+                Some(kind) => {
+                    settings.highlighter.insert(syntax, kind);
+                }
+                None => invalid(&key, "must be syntect or tree-sitter"),
+            }
+        }
+        let mut pairs: Vec<_> = layer.pairs.into_iter().collect();
+        pairs.sort();
+        for (syntax, chars) in pairs {
+            if pairs::is_valid(&chars) {
+                settings.pairs.insert(syntax.to_lowercase(), chars);
+            } else {
+                invalid(&format!("pairs.{}", syntax), "must list opening and closing characters in pairs");
+            }
+        }
+
+        let errors = &mut self.errors;
+        apply_keys(&mut self.editor_keys, &layer.keys.editor, commands::EDITOR, source, "keys.editor", errors);
+        apply_keys(&mut self.explorer_keys, &layer.keys.explorer, commands::EXPLORER, source, "keys.explorer", errors);
+    }
+}
+
+// `--set theme=Nord` is not valid TOML, values that don't parse as TOML
+// are taken as plain strings.
+fn override_line(pair: &str) -> String {
+    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+    let (key, value) = (key.trim(), value.trim());
+    if toml::from_str::<toml::Table>(&format!("v = {}", value)).is_ok() {
+        format!("{} = {}", key, value)
+    } else {
+        format!("{} = {:?}", key, value)
+    }
+}
+
+pub fn parse_color(text: &str) -> Option<(u8, u8, u8)> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+// A binding to "none" removes the default one.
+fn apply_keys(
+    keymap: &mut Keymap,
+    bindings: &HashMap<String, String>,
+    available: &[Command],
+    source: &str,
+    section: &str,
+    errors: &mut Vec<String>,
+) {
+    for (keys, name) in bindings {
+        let sequence = match keymap::parse_sequence(keys) {
+            Ok(sequence) => sequence,
+            Err(err) => {
+                errors.push(format!("{}: [{}] {}", source, section, err));
+                continue;
+            }
+        };
+        if name == "none" {
+            keymap.unbind(&sequence);
+            continue;
+        }
+        match Command::from_name(name) {
+            Some(command) if available.contains(&command) => keymap.bind(sequence, command),
+            Some(_) => errors.push(format!("{}: [{}] `{}` is not available here", source, section, name)),
+            None => errors.push(format!("{}: [{}] unknown command `{}`", source, section, name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(config: &mut Config, text: &str, source: &str) {
+        config.apply(toml::from_str(text).unwrap(), source);
+    }
+
+    #[test]
+    fn later_layers_win_and_unset_values_fall_through() {
+        let mut config = Config::builtin();
+        apply(&mut config, "tab_width = 8\nline_numbers = false", "global");
+        apply(&mut config, "tab_width = 2", "project");
+        assert_eq!(config.settings.tab_width, 2);
+        assert!(!config.settings.line_numbers);
+        assert!(config.errors.is_empty());
+    }
+
+    #[test]
+    fn invalid_values_keep_the_previous_ones() {
+        let mut config = Config::builtin();
+        apply(&mut config, "tab_width = 8", "global");
+        apply(
+            &mut config,
+            "tab_width = 0\nbackground = \"blue\"",
+            "project",
+        );
+        assert_eq!(config.settings.tab_width, 8);
+        assert_eq!(config.settings.background, None);
+        assert_eq!(
+            config.errors,
+            [
+                "project: `tab_width` must be between 1 and 16",
+                "project: `background` must be a colour like \"#282a36\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_settings_are_reported() {
+        let mut config = Config::builtin();
+        apply(&mut config, "tabwidth = 8\nexpand_tabs = true", "global");
+        assert!(config.settings.expand_tabs);
+        assert_eq!(config.errors, ["global: unknown setting `tabwidth`"]);
+    }
+
+    #[test]
+    fn key_bindings_can_be_changed_and_removed() {
+        let mut config = Config::builtin();
+        let text = "[keys.editor]\n\"ctrl+e\" = \"move-end\"\n\"ctrl+s\" = \"none\"\n\"ctrl+r\" = \"open\"";
+        apply(&mut config, text, "global");
+        let chord = |text| keymap::Chord::parse(text).unwrap();
+        let keys = &mut config.editor_keys;
+        assert!(matches!(
+            keys.lookup(chord("ctrl+e")),
+            keymap::Lookup::Command(Command::MoveEnd)
+        ));
+        assert!(matches!(
+            keys.lookup(chord("ctrl+s")),
+            keymap::Lookup::Unbound(_)
+        ));
+        assert_eq!(
+            config.errors,
+            ["global: [keys.editor] `open` is not available here"]
+        );
+    }
+
+    #[test]
+    fn overrides_may_be_plain_strings() {
+        assert_eq!(override_line("theme=Nord"), "theme = \"Nord\"");
+        assert_eq!(override_line("tab_width = 2"), "tab_width = 2");
+        assert_eq!(override_line("theme=\"Nord\""), "theme = \"Nord\"");
+    }
+
+    #[test]
+    fn colours_are_hex_triplets() {
+        assert_eq!(parse_color("#282a36"), Some((0x28, 0x2a, 0x36)));
+        assert_eq!(parse_color("282a36"), None);
+        assert_eq!(parse_color("#28a36"), None);
+        assert_eq!(parse_color("#2ü2a36"), None);
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use ec4rs::property::{
+    Charset, EndOfLine, FinalNewline, IndentSize, IndentStyle, TabWidth, TrimTrailingWs,
+};
+
+use crate::config::Settings;
+use crate::fileformat::{Encoding, LineEnding};
+
+// Properties from the `.editorconfig` files that apply to one file; unset
+// properties keep kk's own behaviour.
+#[derive(Default, Clone)]
+pub struct FileStyle {
+    pub expand_tabs: Option<bool>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub line_ending: Option<LineEnding>,
+    pub encoding: Option<Encoding>,
+    pub trim_trailing_whitespace: bool,
+    pub final_newline: Option<bool>,
+}
+
+impl FileStyle {
+    pub fn resolve(path: &Path) -> Result<Self, String> {
+        let mut properties = ec4rs::properties_of(path).map_err(|err| err.to_string())?;
+        properties.use_fallbacks();
+
+        let mut style = FileStyle::default();
+        if let Ok(indent_style) = properties.get::<IndentStyle>() {
+            style.expand_tabs = Some(indent_style == IndentStyle::Spaces);
+        }
+        if let Ok(IndentSize::Value(size @ 1..=16)) = properties.get::<IndentSize>() {
+            style.indent_size = Some(size);
+        }
+        if let Ok(TabWidth::Value(width @ 1..=16)) = properties.get::<TabWidth>() {
+            style.tab_width = Some(width);
+        }
+        style.line_ending = match properties.get::<EndOfLine>() {
+            Ok(EndOfLine::Lf) => Some(LineEnding::Lf),
+            Ok(EndOfLine::CrLf) => Some(LineEnding::CrLf),
+            Ok(EndOfLine::Cr) => Some(LineEnding::Cr),
+            Err(_) => None,
+        };
+        style.encoding = match properties.get::<Charset>() {
+            Ok(Charset::Utf8) => Some(Encoding::Utf8),
+            Ok(Charset::Utf8Bom) => Some(Encoding::Utf8Bom),
+            Ok(Charset::Latin1) => Some(Encoding::Latin1),
+            Ok(Charset::Utf16Le) => Some(Encoding::Utf16Le),
+            Ok(Charset::Utf16Be) => Some(Encoding::Utf16Be),
+            Err(_) => None,
+        };
+        style.trim_trailing_whitespace = matches!(
+            properties.get::<TrimTrailingWs>(),
+            Ok(TrimTrailingWs::Value(true))
+        );
+        if let Ok(FinalNewline::Value(value)) = properties.get::<FinalNewline>() {
+            style.final_newline = Some(value);
+        }
+        Ok(style)
+    }
+
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(expand_tabs) = self.expand_tabs {
+            settings.expand_tabs = expand_tabs;
+        }
+        if let Some(indent_size) = self.indent_size {
+            settings.indent_size = indent_size;
+        }
+        if let Some(tab_width) = self.tab_width {
+            settings.tab_width = tab_width;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sections_that_match_the_file_are_applied() {
+        let dir = std::env::temp_dir().join(format!("kk-editorconfig-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = "root = true\n\n[*]\nindent_style = space\nindent_size = 2\n\n[*.go]\nindent_style = tab\nend_of_line = crlf\ncharset = latin1\ntrim_trailing_whitespace = true\n";
+        fs::write(dir.join(".editorconfig"), config).unwrap();
+
+        let style = FileStyle::resolve(&dir.join("main.rs")).unwrap();
+        assert_eq!(style.expand_tabs, Some(true));
+        assert_eq!(style.indent_size, Some(2));
+        // tab_width falls back to indent_size.
+        assert_eq!(style.tab_width, Some(2));
+        assert_eq!(style.line_ending, None);
+        assert!(!style.trim_trailing_whitespace);
+
+        let style = FileStyle::resolve(&dir.join("main.go")).unwrap();
+        assert_eq!(style.expand_tabs, Some(false));
+        assert_eq!(style.line_ending, Some(LineEnding::CrLf));
+        assert_eq!(style.encoding, Some(Encoding::Latin1));
+        assert!(style.trim_trailing_whitespace);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unset_properties_keep_the_settings() {
+        let mut settings = Settings::default();
+        let style = FileStyle {
+            tab_width: Some(8),
+            ..FileStyle::default()
+        };
+        style.apply(&mut settings);
+        assert_eq!(settings.tab_width, 8);
+        assert_eq!(settings.indent_size, Settings::default().indent_size);
+        assert_eq!(settings.expand_tabs, Settings::default().expand_tabs);
+    }
+}
@@ -0,0 +1,183 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn detect(content: &str) -> Self {
+        match content.find(['\n', '\r']) {
+            Some(i) if content[i..].starts_with("\r\n") => LineEnding::CrLf,
+            Some(i) if content[i..].starts_with('\r') => LineEnding::Cr,
+            _ => LineEnding::Lf,
+        }
+    }
+
+    pub fn split(&self, content: &str) -> Vec<String> {
+        match self {
+            LineEnding::Cr => content
+                .strip_suffix('\r')
+                .unwrap_or(content)
+                .split('\r')
+                .map(|l| l.to_string())
+                .collect(),
+            _ => content.lines().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    // Guesses the encoding from the byte order mark, anything else is read
+    // as UTF-8.
+    pub fn decode(bytes: &[u8]) -> Option<(Self, String)> {
+        let encoding = if bytes.starts_with(b"\xEF\xBB\xBF") {
+            Encoding::Utf8Bom
+        } else if bytes.starts_with(b"\xFF\xFE") {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(b"\xFE\xFF") {
+            Encoding::Utf16Be
+        } else {
+            Encoding::Utf8
+        };
+        let content = encoding.decode_as(bytes)?;
+        Some((encoding, content))
+    }
+
+    pub fn decode_as(&self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).ok()
+            }
+            Encoding::Latin1 => Some(bytes.iter().map(|&b| b as char).collect()),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+                if !bytes.len().is_multiple_of(2) {
+                    return None;
+                }
+                let units = bytes.chunks(2).map(|pair| match self {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                });
+                char::decode_utf16(units).collect::<Result<_, _>>().ok()
+            }
+        }
+    }
+
+    fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 | Encoding::Latin1 => b"",
+            Encoding::Utf8Bom => b"\xEF\xBB\xBF",
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf16Be => b"\xFE\xFF",
+        }
+    }
+
+    // Characters that don't fit in Latin-1 are written as '?'.
+    pub fn encode(&self, content: &str) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(content.len() + 3);
+        bytes.extend_from_slice(self.bom());
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => bytes.extend_from_slice(content.as_bytes()),
+            Encoding::Latin1 => {
+                bytes.extend(content.chars().map(|c| u8::try_from(c).unwrap_or(b'?')))
+            }
+            Encoding::Utf16Le => {
+                bytes.extend(content.encode_utf16().flat_map(|unit| unit.to_le_bytes()))
+            }
+            Encoding::Utf16Be => {
+                bytes.extend(content.encode_utf16().flat_map(|unit| unit.to_be_bytes()))
+            }
+        }
+        bytes
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Latin1 => "latin1",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_endings_are_detected_from_the_first_line() {
+        assert_eq!(LineEnding::detect("a\r\nb\nc"), LineEnding::CrLf);
+        assert_eq!(LineEnding::detect("a\nb\r\nc"), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("one line"), LineEnding::Lf);
+    }
+
+    #[test]
+    fn crlf_lines_are_split_without_the_cr() {
+        let lines = LineEnding::CrLf.split("a\r\nb\r\n");
+        assert_eq!(lines, ["a", "b"]);
+    }
+
+    #[test]
+    fn a_byte_order_mark_is_kept_for_saving() {
+        let (encoding, content) = Encoding::decode(b"\xEF\xBB\xBFtext").unwrap();
+        assert_eq!(encoding, Encoding::Utf8Bom);
+        assert_eq!(content, "text");
+        assert_eq!(encoding.encode(&content), b"\xEF\xBB\xBFtext");
+    }
+
+    #[test]
+    fn invalid_utf8_is_not_decoded() {
+        assert!(Encoding::decode(b"caf\xE9").is_none());
+    }
+
+    #[test]
+    fn cr_lines_keep_an_empty_last_line_only_once() {
+        assert_eq!(LineEnding::detect("a\rb"), LineEnding::Cr);
+        assert_eq!(LineEnding::Cr.split("a\rb\r"), ["a", "b"]);
+        assert_eq!(LineEnding::Cr.split("a\r\r"), ["a", ""]);
+    }
+
+    #[test]
+    fn latin1_round_trips_and_replaces_what_does_not_fit() {
+        assert_eq!(Encoding::Latin1.decode_as(b"caf\xE9").unwrap(), "café");
+        assert_eq!(Encoding::Latin1.encode("café €"), b"caf\xE9 ?");
+    }
+
+    #[test]
+    fn utf16_is_recognised_by_its_byte_order_mark() {
+        let (encoding, content) = Encoding::decode(b"\xFF\xFEh\0i\0").unwrap();
+        assert_eq!(encoding, Encoding::Utf16Le);
+        assert_eq!(content, "hi");
+        assert_eq!(Encoding::Utf16Be.encode("hi"), b"\xFE\xFF\0h\0i");
+        assert!(Encoding::decode(b"\xFE\xFF\0h\0").is_none());
+    }
+}
@@ -1,11 +1,17 @@
 use std::fs;
-use std::io::{Write, stdout};
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::IntoRawMode;
+use std::time::Duration;
 use termion::{clear, cursor, style};
-use termion::{color, screen};
+
+use crate::colors;
+use crate::commands::{self, Command};
+use crate::config::Config;
+use crate::keymap::{self, Chord, Keymap, Lookup};
+use crate::message::Messages;
+use crate::prompt::{self, Prompt};
+use crate::terminal::{TermEvent, Terminal};
+use crate::theme::{self, Palette};
 
 #[derive(Debug, Clone)]
 
@@ -20,19 +26,34 @@ pub struct Explorer {
     pub current_dir: PathBuf,
     pub entries: Vec<DirEntry>,
     pub selected: usize,
-    pub is_open: bool,
     pub scroll_offset: usize,
+    pub messages: Messages,
+    keymap: Keymap,
+    pub config: Config,
+    palette: Palette,
+}
+
+enum Outcome {
+    Stay,
+    Quit,
+    Open(PathBuf),
 }
 
 impl Explorer {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         let cwd = std::env::current_dir().unwrap();
+        colors::set_mode(config.settings.colors);
+        let mut messages = Messages::default();
+        messages.set_timeout(Duration::from_secs(config.settings.message_timeout));
         let mut explorer = Explorer {
             entries: vec![],
             selected: 0,
-            is_open: false,
             current_dir: cwd.clone(),
             scroll_offset: 0,
+            messages,
+            keymap: config.explorer_keys.clone(),
+            config: config.clone(),
+            palette: theme_palette(config),
         };
         explorer.load_dir(&cwd);
         explorer
@@ -56,18 +77,23 @@ impl Explorer {
             });
         }
 
-        if let Ok(read_dir) = fs::read_dir(path) {
-            let mut entries: Vec<DirEntry> = read_dir
-                .filter_map(|e| e.ok())
-                .map(|e| {
-                    let path = e.path();
-                    let is_dir = path.is_dir();
-                    let name = e.file_name().to_string_lossy().to_string();
-                    DirEntry { name, path, is_dir }
-                })
-                .collect();
-            entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
-            self.entries.extend(entries);
+        match fs::read_dir(path) {
+            Ok(read_dir) => {
+                let mut entries: Vec<DirEntry> = read_dir
+                    .filter_map(|e| e.ok())
+                    .map(|e| {
+                        let path = e.path();
+                        let is_dir = path.is_dir();
+                        let name = e.file_name().to_string_lossy().to_string();
+                        DirEntry { name, path, is_dir }
+                    })
+                    .collect();
+                entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
+                self.entries.extend(entries);
+            }
+            Err(err) => self
+                .messages
+                .error(format!("Can't read {}: {}", path.display(), err)),
         }
     }
     pub fn enter(&mut self) -> Option<PathBuf> {
@@ -106,13 +132,36 @@ impl Explorer {
         }
     }
 
+    // Asks for a path; directories are opened in place, files are returned.
+    fn go_to(&mut self, term: &mut Terminal) -> io::Result<Option<PathBuf>> {
+        let initial = format!("{}/", self.current_dir.display());
+        let input = Prompt::new("path", "Go to: ")
+            .initial(initial.trim_start_matches("//"))
+            .completer(Box::new(prompt::complete_path))
+            .run(term, |term| self.redraw(term))?;
+
+        let Some(input) = input.filter(|input| !input.is_empty()) else {
+            return Ok(None);
+        };
+        let path = self.current_dir.join(prompt::expand_home(&input));
+        if path.is_dir() {
+            self.load_dir(&path);
+        } else if path.is_file() {
+            return Ok(Some(path));
+        } else {
+            self.messages
+                .error(format!("No such file or directory: {}", path.display()));
+        }
+        Ok(None)
+    }
+
     pub fn render<W: Write>(&self, stdout: &mut W, height: u16) {
         write!(
             stdout,
             "{}{}{}{}{}",
             cursor::Goto(1, 1),
-            color::Bg(color::Black),
-            color::Fg(color::Yellow),
+            colors::Bg(self.palette.background),
+            colors::Fg(self.palette.accent),
             self.current_dir.display(),
             style::Reset,
         )
@@ -135,11 +184,12 @@ impl Explorer {
             if is_select {
                 write!(
                     stdout,
-                    "{}{}{}{}{}{}{}",
+                    "{}{}{}{}{}{}{}{}",
                     cursor::Goto(1, row),
-                    color::Bg(color::Rgb(60, 60, 60)),
-                    color::Fg(color::Yellow),
+                    colors::Bg(self.palette.selection),
+                    colors::Fg(self.palette.accent),
                     clear::CurrentLine,
+                    colors::reverse(),
                     arrow,
                     icon,
                     entry.name,
@@ -150,8 +200,8 @@ impl Explorer {
                     stdout,
                     "{}{}{}{}{}{}",
                     cursor::Goto(1, row),
-                    color::Bg(color::Black),
-                    color::Fg(color::Yellow),
+                    colors::Bg(self.palette.background),
+                    colors::Fg(self.palette.accent),
                     arrow,
                     icon,
                     entry.name,
@@ -162,89 +212,146 @@ impl Explorer {
                     stdout,
                     "{}{}{}{}{}{}",
                     cursor::Goto(1, row),
-                    color::Bg(color::Black),
-                    color::Fg(color::White),
+                    colors::Bg(self.palette.background),
+                    colors::Fg(self.palette.foreground),
                     arrow,
                     icon,
                     entry.name,
                 )
                 .unwrap();
             }
-            let total = self.entries.len();
+        }
+
+        if self.messages.current().is_some() {
+            self.messages.draw(stdout, height).unwrap();
+        } else {
             write!(
                 stdout,
                 "{}{}{}  {}/{}  {}",
                 cursor::Goto(1, height),
-                color::Bg(color::Black),
-                color::Fg(color::Yellow),
+                colors::Bg(self.palette.background),
+                colors::Fg(self.palette.accent),
                 self.selected + 1,
-                total,
+                self.entries.len(),
                 style::Reset,
             )
             .unwrap();
-
-            write!(stdout, "{}", style::Reset).unwrap();
         }
 
         stdout.flush().unwrap();
     }
 
-    pub fn run(&mut self) -> Option<PathBuf> {
-        let stdin = std::io::stdin();
-        let mut stdout = stdout().into_raw_mode().unwrap();
-
+    pub fn run(&mut self, term: &mut Terminal) -> io::Result<Option<PathBuf>> {
         write!(
-            stdout,
+            term,
             "{}{}{}",
             cursor::Hide,
-            color::Bg(color::Black),
+            colors::Bg(self.palette.background),
             clear::All,
-        )
-        .unwrap();
-        stdout.flush().unwrap();
+        )?;
+        term.flush()?;
 
-        let (_, height) = termion::terminal_size().unwrap();
-        self.render(&mut stdout, height);
+        let (_, height) = term.size();
+        self.render(term, height);
 
-        for key in stdin.keys() {
-            let (_, height) = termion::terminal_size().unwrap();
+        loop {
+            let chord = match term.poll_event(self.messages.time_left())? {
+                Some(TermEvent::Input(evt)) => Chord::from_event(&evt),
+                _ => None,
+            };
+            let (_, height) = term.size();
             let visible_count = (height as usize).saturating_sub(2);
 
-            match key.unwrap() {
-                Key::Up => {
-                    self.move_up();
-                    self.update_scroll(visible_count);
+            let outcome = match chord.map(|chord| self.keymap.lookup(chord)) {
+                Some(Lookup::Command(command)) => self.execute(term, command)?,
+                Some(Lookup::Unbound(keys)) if keys.len() > 1 => {
+                    self.messages
+                        .warning(format!("{} is not bound", keymap::format_sequence(&keys)));
+                    Outcome::Stay
                 }
-                Key::Down => {
-                    self.move_down();
-                    self.update_scroll(visible_count);
+                _ => Outcome::Stay,
+            };
+            match outcome {
+                Outcome::Stay => self.update_scroll(visible_count),
+                Outcome::Quit => break,
+                Outcome::Open(filepath) => {
+                    write!(term, "{}{}{}", style::Reset, clear::All, cursor::Show)?;
+                    term.flush()?;
+                    return Ok(Some(filepath));
                 }
-                Key::Char('\n') => {
-                    if let Some(filepath) = self.enter() {
-                        write!(stdout, "{}{}{}", style::Reset, clear::All, cursor::Show).unwrap();
-                        stdout.flush().unwrap();
-                        drop(stdout);
-                        return Some(filepath);
-                    }
-                    self.update_scroll(visible_count);
+            }
+            write!(term, "{}{}", colors::Bg(self.palette.background), clear::All)?;
+            self.render(term, height);
+        }
+        write!(term, "{}{}{}", style::Reset, clear::All, cursor::Show)?;
+        term.flush()?;
+        Ok(None)
+    }
+
+    fn execute(&mut self, term: &mut Terminal, command: Command) -> io::Result<Outcome> {
+        match command {
+            Command::Quit => return Ok(Outcome::Quit),
+            Command::MoveUp => self.move_up(),
+            Command::MoveDown => self.move_down(),
+            Command::Open => {
+                if let Some(filepath) = self.enter() {
+                    return Ok(Outcome::Open(filepath));
+                }
+            }
+            Command::ParentDir => {
+                if let Some(parent_dir) = self.current_dir.parent() {
+                    let parent_dir = parent_dir.to_path_buf();
+                    self.load_dir(&parent_dir);
+                }
+            }
+            Command::GoTo => {
+                if let Some(filepath) = self.go_to(term)? {
+                    return Ok(Outcome::Open(filepath));
+                }
+            }
+            Command::ShowMessages => self.messages.show_history(term)?,
+            Command::ReloadConfig => {
+                self.config = self.config.reload(&self.current_dir);
+                self.keymap = self.config.explorer_keys.clone();
+                self.palette = theme_palette(&self.config);
+                colors::set_mode(self.config.settings.colors);
+                self.messages
+                    .set_timeout(Duration::from_secs(self.config.settings.message_timeout));
+                if self.config.errors.is_empty() {
+                    self.messages.info("Configuration reloaded");
                 }
-                Key::Ctrl('q') => {
-                    write!(stdout, "{}{}{}", style::Reset, clear::All, cursor::Show).unwrap();
-                    break;
+                for error in self.config.errors.drain(..) {
+                    self.messages.error(error);
                 }
-                Key::Ctrl('d') => {
-                    if let Some(parent_dir) = self.current_dir.parent() {
-                        let parent_dir = parent_dir.to_path_buf();
-                        self.load_dir(&parent_dir);
+            }
+            Command::Execute => {
+                let name = Prompt::new("command", "Command: ")
+                    .completer(commands::completer(commands::EXPLORER))
+                    .run(term, |term| self.redraw(term))?;
+                match name.as_deref().map(Command::from_name) {
+                    Some(Some(command)) if commands::EXPLORER.contains(&command) => {
+                        return self.execute(term, command);
                     }
+                    Some(_) => self
+                        .messages
+                        .error(format!("Unknown command: {}", name.unwrap_or_default())),
+                    None => {}
                 }
-                _ => {}
             }
-            write!(stdout, "{}{}", color::Bg(color::Black), clear::All).unwrap();
-            self.render(&mut stdout, height);
+            _ => {}
         }
-        write!(stdout, "{}{}{}", style::Reset, clear::All, cursor::Show).unwrap();
-        stdout.flush().unwrap();
-        None
+        Ok(Outcome::Stay)
     }
+
+    fn redraw(&self, term: &mut Terminal) -> io::Result<()> {
+        let (_, height) = term.size();
+        write!(term, "{}{}", colors::Bg(self.palette.background), clear::All)?;
+        self.render(term, height);
+        Ok(())
+    }
+}
+
+fn theme_palette(config: &Config) -> Palette {
+    let settings = &config.settings;
+    Palette::new(&theme::load_or_default(&settings.theme), settings.background)
 }
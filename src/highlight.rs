@@ -1,90 +1,857 @@
-use syntect::easy::HighlightLines;
-use syntect::highlighting::FontStyle;
-use syntect::highlighting::{Style, ThemeSet};
-use syntect::parsing::SyntaxSet;
-use syntect::util::LinesWithEndings;
-use termion::color;
-use two_face::theme::EmbeddedThemeName;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use syntect::LoadingError;
+use syntect::dumps;
+use syntect::highlighting::{self, HighlightIterator, HighlightState, Style, Theme};
+use syntect::parsing::{
+    Metadata, ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxDefinition, SyntaxSet,
+    SyntaxSetBuilder,
+};
+
+use crate::comment;
+use crate::config;
+use crate::terminal::{HIGHLIGHT_THREAD, TermEvent};
+use crate::treesitter::TreeSitter;
+
+// What a piece of text is, so that bracket matching and the like can leave
+// strings and comments alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Code,
+    String,
+    Comment,
+}
+
+pub type Span = (Style, Kind, String);
+
+// One way of turning lines into spans, it lives on the worker thread.
+pub trait Backend {
+    // Lines `old` of the previous text are now lines `new` of `lines`.
+    fn edit(&mut self, lines: &[String], old: Range<usize>, new: Range<usize>);
+
+    // Highlights `lines` from `row` on and hands the spans of every line to
+    // `emit` until it returns false. `row` is never past the lines
+    // highlighted so far.
+    fn highlight(&mut self, lines: &[String], row: usize, emit: &mut dyn FnMut(Vec<Span>) -> bool);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackendKind {
+    Syntect,
+    TreeSitter,
+}
+
+impl BackendKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "syntect" => Some(BackendKind::Syntect),
+            "tree-sitter" => Some(BackendKind::TreeSitter),
+            _ => None,
+        }
+    }
+}
+
+// Highlighting runs on a worker thread. The editor sends it the lines that
+// changed since the last update, the worker's backend re-highlights from the
+// first of them and the results are published in chunks. Each update bumps
+// the generation, which makes the worker drop what it is doing and start
+// over with the newest text.
 pub struct Highlighter {
-    ps: SyntaxSet,
-    theme: syntect::highlighting::Theme,
-    extension: String,
+    ps: Arc<SyntaxSet>,
+    theme: Theme,
+    syntax: String,
+    // Backend per lower-case syntax name, the rest use tree-sitter when
+    // there is a grammar for them.
+    backends: HashMap<String, BackendKind>,
+    errors: Vec<String>,
+    jobs: Option<Sender<Job>>,
+    shared: Arc<Shared>,
+    // The lines the worker knows about.
+    sent: Vec<String>,
+    generation: u64,
+    reset: bool,
+}
+
+// Replaces lines `start..end` of the worker's copy with `lines`.
+struct Job {
+    generation: u64,
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+    // Replaces the backend and starts over from the first line.
+    reset: Option<Setup>,
+}
+
+// What the worker needs to make a backend.
+struct Setup {
+    kind: BackendKind,
+    syntax: String,
+    theme: Theme,
+}
+
+#[derive(Default)]
+struct Shared {
+    generation: AtomicU64,
+    notified: AtomicBool,
+    results: Mutex<Results>,
+    // Why the worker stopped, when it panicked.
+    failure: Mutex<Option<String>>,
+}
+
+// Spans of every line highlighted so far. Lines after an edit keep their
+// old spans until the worker gets to them.
+#[derive(Default)]
+pub struct Results {
+    lines: Vec<Vec<Span>>,
+}
+
+impl Results {
+    #[cfg(test)]
+    pub fn new(lines: Vec<Vec<Span>>) -> Self {
+        Results { lines }
+    }
+
+    // Spans for line `i` if they are for the text it has now. The buffer
+    // may be a keystroke ahead of the last `update`. None means the line is
+    // drawn plain.
+    pub fn get(&self, i: usize, text: &str) -> Option<&[Span]> {
+        let spans = self.lines.get(i)?;
+        let mut rest = text;
+        for (_, _, token) in spans {
+            rest = rest.strip_prefix(token.as_str())?;
+        }
+        rest.is_empty().then_some(spans)
+    }
+
+    // The kind of every character of line `i`, code where it isn't known
+    // yet.
+    pub fn kinds(&self, i: usize, text: &str) -> Vec<Kind> {
+        match self.get(i, text) {
+            Some(spans) => spans
+                .iter()
+                .flat_map(|(_, kind, token)| token.chars().map(move |_| *kind))
+                .collect(),
+            None => vec![Kind::Code; text.chars().count()],
+        }
+    }
+}
+
+const CHUNK_LINES: usize = 1000;
+const CHUNK_TIME: Duration = Duration::from_millis(30);
+
+const PLAIN_TEXT: &str = "Plain Text";
+
+// User syntaxes are `<config dir>/syntaxes/*.sublime-syntax`, their
+// `.tmPreferences` go next to them.
+fn user_files(extension: &str) -> Vec<PathBuf> {
+    let Some(dir) = config::config_dir().map(|dir| dir.join("syntaxes")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+    files
+}
+
+// What the user files look like, so that what is made from them can be
+// cached until one of them changes.
+fn files_hash(files: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    for path in files {
+        path.hash(&mut hasher);
+        if let Ok(meta) = fs::metadata(path) {
+            meta.len().hash(&mut hasher);
+            meta.modified().ok().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+// `<kind>-<hash>.bin` from the cache directory, or what `make` returns,
+// which is cached in place of the older ones unless it reports a failure.
+fn cached<T>(kind: &str, hash: u64, make: impl FnOnce() -> (T, bool)) -> T
+where
+    T: Serialize + DeserializeOwned,
+{
+    let cache_name = format!("{}-{:016x}.bin", kind, hash);
+    let cache_dir = config::cache_dir();
+    let cache = cache_dir.as_ref().map(|dir| dir.join(&cache_name));
+    if let Some(value) = cache
+        .as_ref()
+        .and_then(|path| dumps::from_uncompressed_dump_file(path).ok())
+    {
+        return value;
+    }
+
+    let (value, failed) = make();
+
+    // A broken file is compiled again next time so its error is reported.
+    if let (Some(dir), Some(cache)) = (cache_dir, cache)
+        && !failed
+    {
+        let _ = fs::create_dir_all(&dir);
+        let prefix = format!("{}-", kind);
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(&prefix) && name != cache_name {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        let _ = dumps::dump_to_uncompressed_file(&value, &cache);
+    }
+    value
+}
+
+// Comment tokens and the like from the user `.tmPreferences`. They aren't
+// part of the cached set, syntect doesn't serialise them, and only a
+// builder loads them, so they are cached on their own.
+fn load_metadata(errors: &mut Vec<String>) -> Option<Metadata> {
+    let preferences = user_files("tmPreferences");
+    if preferences.is_empty() {
+        return None;
+    }
+    let dir = config::config_dir()?.join("syntaxes");
+    // The builder stops at a broken syntax, so they count too.
+    let files = [user_files("sublime-syntax"), preferences].concat();
+    let metadata = cached("metadata", files_hash(&files), || {
+        let mut builder = SyntaxSetBuilder::new();
+        let failed = match builder.add_from_folder(&dir, true) {
+            Ok(()) => false,
+            // Broken syntaxes are reported by `load_syntaxes`.
+            Err(LoadingError::ParseSyntax(..)) => true,
+            Err(err) => {
+                errors.push(format!("{}: {}", dir.display(), err));
+                true
+            }
+        };
+        (builder.build().metadata().clone(), failed)
+    });
+    Some(metadata)
+}
+
+// The bundled syntaxes plus the user ones. Linking the whole set takes a
+// while, so it is cached until one of the user files changes.
+fn load_syntaxes(errors: &mut Vec<String>) -> SyntaxSet {
+    let files = user_files("sublime-syntax");
+    if files.is_empty() {
+        return two_face::syntax::extra_newlines();
+    }
+
+    cached("syntaxes", files_hash(&files), || {
+        let mut builder = two_face::syntax::extra_newlines().into_builder();
+        let mut failed = false;
+        for path in &files {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string());
+            let definition = fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| {
+                    SyntaxDefinition::load_from_str(&text, true, name.as_deref())
+                        .map_err(|err| err.to_string())
+                });
+            match definition {
+                Ok(definition) => builder.add(definition),
+                Err(err) => {
+                    errors.push(format!("{}: {}", path.display(), err));
+                    failed = true;
+                }
+            }
+        }
+        (builder.build(), failed)
+    })
+}
+
+// Syntaxes list whole file names such as `Makefile` or `.bashrc` along
+// with their extensions. Short upper-case entries like `C` and `R` are
+// extensions too.
+fn is_file_name(entry: &str) -> bool {
+    entry.starts_with('.')
+        || entry.contains(['-', '_'])
+        || (entry.len() > 3 && entry.chars().any(|c| c.is_ascii_uppercase()))
+}
+
+// `vim: set ft=python:`, `vi: syntax=sh` and the like.
+fn vim_modeline(line: &str) -> Option<&str> {
+    let start = ["vim:", "vi:", "ex:"]
+        .iter()
+        .filter_map(|marker| line.find(marker).map(|i| i + marker.len()))
+        .min()?;
+    line[start..]
+        .split([' ', ':', '\t'])
+        .filter_map(|option| option.split_once('='))
+        .find(|(key, _)| matches!(*key, "ft" | "filetype" | "syntax" | "syn"))
+        .map(|(_, value)| value)
+}
+
+// `-*- mode: python -*-` or just `-*- python -*-`.
+fn emacs_modeline(line: &str) -> Option<&str> {
+    let start = line.find("-*-")? + 3;
+    let inner = &line[start..start + line[start..].find("-*-")?];
+    let mode = match inner.split(';').find_map(|var| {
+        let (key, value) = var.split_once(':')?;
+        (key.trim().eq_ignore_ascii_case("mode")).then_some(value)
+    }) {
+        Some(mode) => mode,
+        None if !inner.contains(':') => inner,
+        None => return None,
+    };
+    Some(mode.trim())
 }
 
 impl Highlighter {
-    pub fn new(filename: &str) -> Self {
-        let extension = filename.rsplit('.').next().unwrap_or("txt").to_string();
-        let ps = two_face::syntax::extra_newlines();
-
-        let found = ps.find_syntax_by_extension(&extension);
-        eprintln!(
-            "Extension: {}, Syntax found: {}",
-            extension,
-            found.map(|s| s.name.as_str()).unwrap_or("NOT FOUND")
-        );
+    pub fn new(theme: Theme, backends: HashMap<String, BackendKind>) -> Self {
+        let mut errors = Vec::new();
+        let mut ps = load_syntaxes(&mut errors);
+        if let Some(metadata) = load_metadata(&mut errors) {
+            ps.set_metadata(metadata);
+        }
+        let ps = Arc::new(ps);
 
-        let theme = two_face::theme::extra()
-            .get(EmbeddedThemeName::Dracula)
-            .clone();
         Highlighter {
             ps,
             theme,
-            extension,
+            syntax: PLAIN_TEXT.to_string(),
+            backends,
+            errors,
+            jobs: None,
+            shared: Arc::default(),
+            sent: Vec::new(),
+            generation: 0,
+            reset: true,
+        }
+    }
+
+    // Starts the worker, `wake` gets a `TermEvent::Highlighted` when new
+    // results are ready.
+    pub fn start(&mut self, wake: Sender<io::Result<TermEvent>>) {
+        if self.jobs.is_some() {
+            return;
+        }
+        let (jobs, receiver) = mpsc::channel();
+        let ps = Arc::clone(&self.ps);
+        let shared = Arc::clone(&self.shared);
+        let setup = self.setup();
+        let worker = move || {
+            let worker = AssertUnwindSafe(|| work(&ps, setup, &shared, receiver, wake.clone()));
+            if let Err(payload) = panic::catch_unwind(worker) {
+                let reason = match payload.downcast::<String>() {
+                    Ok(reason) => *reason,
+                    Err(payload) => match payload.downcast::<&str>() {
+                        Ok(reason) => reason.to_string(),
+                        Err(_) => "it panicked".to_string(),
+                    },
+                };
+                *shared.failure.lock().unwrap() = Some(reason);
+                let _ = wake.send(Ok(TermEvent::Highlighted));
+            }
+        };
+        let spawned = thread::Builder::new()
+            .name(HIGHLIGHT_THREAD.to_string())
+            .spawn(worker);
+        if let Err(err) = spawned {
+            self.errors.push(format!("Can't start highlighting: {}", err));
         }
+        self.jobs = Some(jobs);
+        self.reset = false;
     }
-    pub fn highlight_all(&self, lines: &[String]) -> Vec<String> {
-        let syntax = self
+
+    fn setup(&self) -> Setup {
+        let kind = match self.backends.get(&self.syntax.to_lowercase()) {
+            Some(&kind) => kind,
+            None if TreeSitter::supports(&self.syntax) => BackendKind::TreeSitter,
+            None => BackendKind::Syntect,
+        };
+        Setup {
+            kind,
+            syntax: self.syntax.clone(),
+            theme: self.theme.clone(),
+        }
+    }
+
+    // Sends the worker whatever changed since the last call.
+    pub fn update(&mut self, lines: &[String]) {
+        if self.jobs.is_none() {
+            return;
+        }
+        // Only the lines between the unchanged head and tail are sent.
+        let same = |(a, b): &(&String, &String)| a == b;
+        let start = self.sent.iter().zip(lines).take_while(same).count();
+        if start == lines.len() && start == self.sent.len() && !self.reset {
+            return;
+        }
+        let shortest = lines.len().min(self.sent.len()) - start;
+        let tail = self.sent.iter().rev().zip(lines.iter().rev());
+        let tail = tail.take(shortest).take_while(same).count();
+        let end = self.sent.len() - tail;
+        let changed = lines[start..lines.len() - tail].to_vec();
+        self.sent.splice(start..end, changed.iter().cloned());
+        self.generation += 1;
+
+        {
+            let mut results = self
+                .shared
+                .results
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            self.shared
+                .generation
+                .store(self.generation, Ordering::SeqCst);
+            // Keep the old spans of the lines after the change next to their
+            // text, they are shown until the worker gets there.
+            if start < results.lines.len() {
+                let end = end.min(results.lines.len());
+                results
+                    .lines
+                    .splice(start..end, changed.iter().map(|_| Vec::new()));
+            }
+        }
+        let reset = std::mem::take(&mut self.reset).then(|| self.setup());
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(Job {
+                generation: self.generation,
+                start,
+                end,
+                lines: changed,
+                reset,
+            });
+        }
+    }
+
+    // Reading the results re-arms the wake-up event.
+    pub fn results(&self) -> MutexGuard<'_, Results> {
+        self.shared.notified.store(false, Ordering::SeqCst);
+        // A worker that panicked may have left them half done, they are
+        // checked against the text anyway.
+        self.shared
+            .results
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Problems with the user syntaxes and a worker that stopped, for the
+    // message line.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        if let Some(reason) = self.shared.failure.lock().unwrap().take() {
+            self.errors
+                .push(format!("Highlighting stopped: {}", reason));
+        }
+        std::mem::take(&mut self.errors)
+    }
+
+    // A modeline wins, then the file name and extension, then the first
+    // line (shebangs, `<?xml` and so on).
+    pub fn detect(&mut self, filename: &str, lines: &[String]) {
+        let head = lines.iter().take(5);
+        let tail = lines.iter().skip(5).rev().take(5);
+        let modeline = head
+            .chain(tail)
+            .find_map(|line| vim_modeline(line).or_else(|| emacs_modeline(line)))
+            .and_then(|name| self.ps.find_syntax_by_token(name));
+
+        let path = std::path::Path::new(filename);
+        let name = path.file_name().map(|name| name.to_string_lossy());
+        let by_name = name.and_then(|name| {
+            let whole = self.ps.syntaxes().iter().rev().find(|syntax| {
+                let mut names = syntax.file_extensions.iter();
+                names.any(|entry| is_file_name(entry) && entry.eq_ignore_ascii_case(&name))
+            });
+            whole.or_else(|| {
+                let extension = name.rsplit_once('.')?.1;
+                self.ps.find_syntax_by_extension(extension)
+            })
+        });
+
+        let syntax = modeline
+            .or(by_name)
+            .or_else(|| self.ps.find_syntax_by_first_line(lines.first()?));
+        self.syntax = syntax.map_or(PLAIN_TEXT, |s| s.name.as_str()).to_string();
+        self.reset = true;
+    }
+
+    pub fn set_syntax(&mut self, name: &str) {
+        self.syntax = name.to_string();
+        self.reset = true;
+    }
+
+    // Names for the syntax picker, hidden helper syntaxes left out.
+    pub fn syntax_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
             .ps
-            .find_syntax_by_extension(&self.extension)
-            .unwrap_or_else(|| self.ps.find_syntax_plain_text());
+            .syntaxes()
+            .iter()
+            .filter(|s| !s.hidden)
+            .map(|s| s.name.clone())
+            .collect();
+        names.sort_by_key(|name| name.to_lowercase());
+        names.dedup();
+        names
+    }
 
-        let mut h = HighlightLines::new(syntax, &self.theme);
-        let mut result = Vec::new();
+    pub fn set_backends(&mut self, backends: HashMap<String, BackendKind>) {
+        self.backends = backends;
+        self.reset = true;
+    }
 
-        for line in lines {
-            let text = format!("{}\n", line);
-            let ranges = h.highlight_line(&text, &self.ps).unwrap_or_default();
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.reset = true;
+    }
 
-            let mut colored = String::new();
-            for (style, token) in ranges {
-                let token = token.trim_end_matches('\n');
-                if token.is_empty() {
-                    continue;
-                }
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    pub fn syntax_name(&self) -> &str {
+        &self.syntax
+    }
+
+    // `TM_COMMENT_START` and friends of the current syntax, or the built-in
+    // tokens for it.
+    pub fn comment_tokens(&self) -> Option<comment::Tokens> {
+        let syntax = self.ps.find_syntax_by_name(&self.syntax)?;
+        let metadata = self.ps.metadata().metadata_for_scope(&[syntax.scope]);
+        let line = metadata
+            .line_comment()
+            .map(|token| token.trim().to_string());
+        let block = metadata
+            .block_comment()
+            .map(|(start, end)| (start.trim().to_string(), end.trim().to_string()));
+        if line.is_none() && block.is_none() {
+            return comment::builtin(&self.syntax);
+        }
+        Some(comment::Tokens { line, block })
+    }
+}
 
-                let fg = style.foreground;
-                let bg = style.background;
+struct Syntect {
+    ps: Arc<SyntaxSet>,
+    syntax: String,
+    theme: Theme,
+    // The parser state at the start of every line highlighted so far.
+    states: Vec<(ParseState, HighlightState)>,
+}
 
-                colored.push_str(&format!(
-                    "{}",
-                    termion::color::Bg(termion::color::Rgb(bg.r, bg.g, bg.b))
-                ));
-                colored.push_str(&format!(
-                    "{}",
-                    termion::color::Fg(termion::color::Rgb(fg.r, fg.g, fg.b))
-                ));
+impl Backend for Syntect {
+    // The states of the lines after the change are dropped when
+    // highlighting starts over from it.
+    fn edit(&mut self, _: &[String], _: Range<usize>, _: Range<usize>) {}
 
-                if style.font_style.contains(FontStyle::BOLD) {
-                    colored.push_str(&format!("{}", termion::style::Bold));
-                }
-                if style.font_style.contains(FontStyle::ITALIC) {
-                    colored.push_str(&format!("{}", termion::style::Italic));
+    fn highlight(&mut self, lines: &[String], row: usize, emit: &mut dyn FnMut(Vec<Span>) -> bool) {
+        let ps = &*self.ps;
+        let highlighter = highlighting::Highlighter::new(&self.theme);
+        if self.states.is_empty() {
+            let syntax = ps
+                .find_syntax_by_name(&self.syntax)
+                .unwrap_or_else(|| ps.find_syntax_plain_text());
+            let highlight = HighlightState::new(&highlighter, ScopeStack::new());
+            self.states.push((ParseState::new(syntax), highlight));
+        }
+        self.states.truncate(row + 1);
+        let (mut parse, mut highlight) = self.states[row].clone();
+
+        for line in &lines[row..] {
+            let line = format!("{}\n", line);
+            let ops = parse.parse_line(&line, ps).unwrap_or_default();
+            let kinds = kinds(highlight.path.clone(), &ops);
+            let text_end = line.len() - 1;
+            let mut spans = Vec::new();
+            let mut start = 0;
+            for (style, token) in HighlightIterator::new(&mut highlight, &ops, &line, &highlighter)
+            {
+                // Tokens of the same colour may be partly code and partly
+                // string, they are split where the kind changes.
+                let end = start + token.len();
+                for (i, &(at, kind)) in kinds.iter().enumerate() {
+                    let next = kinds.get(i + 1).map_or(usize::MAX, |&(at, _)| at);
+                    let (from, to) = (start.max(at), end.min(next).min(text_end));
+                    if from < to {
+                        spans.push((style, kind, line[from..to].to_string()));
+                    }
                 }
-                if style.font_style.contains(FontStyle::UNDERLINE) {
-                    colored.push_str(&format!("{}", termion::style::Underline));
+                start = end;
+            }
+            self.states.push((parse.clone(), highlight.clone()));
+            if !emit(spans) {
+                break;
+            }
+        }
+    }
+}
+
+// Tree-sitter for the syntaxes it has a grammar for, unless it's turned off
+// for them, syntect for the rest.
+fn backend(ps: &Arc<SyntaxSet>, setup: Setup) -> Box<dyn Backend> {
+    if setup.kind == BackendKind::TreeSitter
+        && let Ok(backend) = TreeSitter::new(&setup.syntax, &setup.theme)
+    {
+        return Box::new(backend);
+    }
+    Box::new(Syntect {
+        ps: Arc::clone(ps),
+        syntax: setup.syntax,
+        theme: setup.theme,
+        states: Vec::new(),
+    })
+}
+
+// Where the kind changes along a line, worked out from the scope stack at
+// its start and the parser's operations.
+fn kinds(mut path: ScopeStack, ops: &[(usize, ScopeStackOp)]) -> Vec<(usize, Kind)> {
+    static SCOPES: OnceLock<(Scope, Scope)> = OnceLock::new();
+    let (string, comment) = *SCOPES.get_or_init(|| {
+        (
+            Scope::new("string").unwrap(),
+            Scope::new("comment").unwrap(),
+        )
+    });
+    let kind_of = |path: &ScopeStack| {
+        path.as_slice()
+            .iter()
+            .rev()
+            .find_map(|&scope| {
+                if comment.is_prefix_of(scope) {
+                    Some(Kind::Comment)
+                } else if string.is_prefix_of(scope) {
+                    Some(Kind::String)
+                } else {
+                    None
                 }
+            })
+            .unwrap_or(Kind::Code)
+    };
 
-                colored.push_str(token);
+    let mut kinds = vec![(0, kind_of(&path))];
+    for (at, op) in ops {
+        let _ = path.apply(op);
+        let kind = kind_of(&path);
+        let last = kinds.last_mut().unwrap();
+        if last.0 == *at {
+            last.1 = kind;
+        } else if last.1 != kind {
+            kinds.push((*at, kind));
+        }
+    }
+    kinds
+}
+
+fn work(
+    ps: &Arc<SyntaxSet>,
+    setup: Setup,
+    shared: &Shared,
+    jobs: Receiver<Job>,
+    wake: Sender<io::Result<TermEvent>>,
+) {
+    let mut backend = backend(ps, setup);
+    let mut lines: Vec<String> = Vec::new();
+    let mut generation = 0;
+    let mut next = 0;
+    // Results dropped because the text changed have to be redone.
+    let mut published = 0;
+
+    loop {
+        // Wait when everything is done, or when the text changed and the
+        // job saying how is still on its way.
+        let first = if next >= lines.len() || shared.generation.load(Ordering::SeqCst) != generation
+        {
+            match jobs.recv() {
+                Ok(job) => Some(job),
+                Err(_) => return,
+            }
+        } else {
+            None
+        };
+        for job in first.into_iter().chain(jobs.try_iter()) {
+            if let Some(setup) = job.reset {
+                backend = self::backend(ps, setup);
+                next = 0;
+                published = 0;
+            }
+            let new = job.start..job.start + job.lines.len();
+            lines.splice(job.start..job.end, job.lines);
+            backend.edit(&lines, job.start..job.end, new);
+            generation = job.generation;
+            next = next.min(job.start).min(published);
+        }
+
+        let mut chunk = Vec::new();
+        let mut last_publish = Instant::now();
+        backend.highlight(&lines, next, &mut |spans| {
+            chunk.push(spans);
+            next += 1;
+            if chunk.len() >= CHUNK_LINES || last_publish.elapsed() >= CHUNK_TIME {
+                if publish(shared, &wake, generation, next, &mut chunk, None) {
+                    published = next;
+                }
+                last_publish = Instant::now();
             }
-            colored.push_str(&format!(
-                "{}{}",
-                termion::style::Reset,
-                termion::color::Bg(termion::color::Rgb(40, 42, 54))
-            ));
+            shared.generation.load(Ordering::SeqCst) == generation
+        });
+        let total = (next >= lines.len()).then_some(lines.len());
+        if publish(shared, &wake, generation, next, &mut chunk, total) {
+            published = next;
+        }
+    }
+}
 
-            result.push(colored);
+// Stores the spans of the lines before `end`, unless the text changed in
+// the meantime. `total` trims the results once the whole file is done.
+// Returns whether the chunk was kept.
+fn publish(
+    shared: &Shared,
+    wake: &Sender<io::Result<TermEvent>>,
+    generation: u64,
+    end: usize,
+    chunk: &mut Vec<Vec<Span>>,
+    total: Option<usize>,
+) -> bool {
+    let start = end - chunk.len();
+    {
+        let mut results = shared.results.lock().unwrap();
+        if shared.generation.load(Ordering::SeqCst) != generation {
+            chunk.clear();
+            return false;
+        }
+        for (i, spans) in chunk.drain(..).enumerate() {
+            match results.lines.get_mut(start + i) {
+                Some(line) => *line = spans,
+                None => results.lines.push(spans),
+            }
         }
-        result
+        if let Some(total) = total {
+            results.lines.truncate(total);
+        }
+    }
+    if !shared.notified.swap(true, Ordering::SeqCst) {
+        let _ = wake.send(Ok(TermEvent::Highlighted));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(filename: &str, lines: &[&str]) -> String {
+        let mut highlighter = Highlighter::new(Theme::default(), HashMap::new());
+        let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        highlighter.detect(filename, &lines);
+        highlighter.syntax
+    }
+
+    #[test]
+    fn vim_modelines_name_the_file_type() {
+        assert_eq!(vim_modeline("# vim: set ft=python:"), Some("python"));
+        assert_eq!(vim_modeline("// vi: ts=4 syntax=sh"), Some("sh"));
+        assert_eq!(vim_modeline("# vim: ts=4"), None);
+        assert_eq!(vim_modeline("ft=python"), None);
+    }
+
+    #[test]
+    fn emacs_modelines_may_leave_out_the_key() {
+        assert_eq!(
+            emacs_modeline("# -*- mode: ruby; coding: utf-8 -*-"),
+            Some("ruby")
+        );
+        assert_eq!(emacs_modeline("# -*- python -*-"), Some("python"));
+        assert_eq!(emacs_modeline("# -*- coding: utf-8 -*-"), None);
+        assert_eq!(emacs_modeline("# -*- python"), None);
+    }
+
+    #[test]
+    fn short_upper_case_entries_are_extensions() {
+        assert!(is_file_name("Makefile"));
+        assert!(is_file_name(".bashrc"));
+        assert!(is_file_name("PKGBUILD"));
+        assert!(!is_file_name("C"));
+        assert!(!is_file_name("go"));
+    }
+
+    #[test]
+    fn syntaxes_are_detected_by_name_modeline_and_first_line() {
+        assert_eq!(detect("src/main.rs", &[]), "Rust");
+        assert_eq!(detect("Makefile", &[]), "Makefile");
+        assert_eq!(detect("go", &[]), PLAIN_TEXT);
+        assert_eq!(detect("run", &["#!/usr/bin/env python3"]), "Python");
+        assert_eq!(detect("notes.txt", &["# vim: ft=rust"]), "Rust");
+    }
+
+    #[test]
+    fn the_cache_is_keyed_by_the_user_files() {
+        let dir = std::env::temp_dir().join(format!("kk-syntaxes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Foo.sublime-syntax");
+        fs::write(&path, "name: Foo").unwrap();
+        let files = vec![path.clone()];
+        let before = files_hash(&files);
+        assert_eq!(files_hash(&files), before);
+        fs::write(&path, "name: Foo Bar").unwrap();
+        assert_ne!(files_hash(&files), before);
+        assert_ne!(files_hash(&[]), before);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn results() -> Results {
+        let span = |kind, text: &str| (Style::default(), kind, text.to_string());
+        Results::new(vec![vec![
+            span(Kind::Code, "x = "),
+            span(Kind::String, "'é'"),
+        ]])
+    }
+
+    #[test]
+    fn spans_are_only_used_for_the_text_they_were_made_for() {
+        let results = results();
+        assert_eq!(results.get(0, "x = 'é'").unwrap().len(), 2);
+        assert!(results.get(0, "x = 'é'!").is_none());
+        assert!(results.get(0, "y = 'é'").is_none());
+        assert!(results.get(1, "").is_none());
+    }
+
+    #[test]
+    fn kinds_are_per_character() {
+        let results = results();
+        let kinds = results.kinds(0, "x = 'é'");
+        assert_eq!(kinds.len(), 7);
+        assert_eq!(kinds[3], Kind::Code);
+        assert_eq!(kinds[5], Kind::String);
+        assert_eq!(results.kinds(0, "stale"), [Kind::Code; 5]);
+    }
+
+    #[test]
+    fn a_worker_that_panicked_is_reported_once() {
+        let mut highlighter = Highlighter::new(Theme::default(), HashMap::new());
+        let shared = Arc::clone(&highlighter.shared);
+        let worker = thread::spawn(move || {
+            let _results = shared.results.lock().unwrap();
+            *shared.failure.lock().unwrap() = Some("boom".to_string());
+            panic!("boom");
+        });
+        assert!(worker.join().is_err());
+
+        assert!(highlighter.results().get(0, "").is_none());
+        assert_eq!(highlighter.take_errors(), ["Highlighting stopped: boom"]);
+        assert!(highlighter.take_errors().is_empty());
     }
 }
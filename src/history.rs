@@ -0,0 +1,297 @@
+use std::ops::Range;
+
+// Undo and redo. Every edit records the lines it is about to change, the
+// new text of those lines is taken once the command is done.
+
+type Cursor = (usize, usize);
+
+// What an edit does, consecutive typing and deleting are undone together.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Insert,
+    Delete,
+    Other,
+}
+
+// Lines `start..start + new.len()` used to be `old`.
+struct Change {
+    start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+// One undo step.
+struct Step {
+    kind: EditKind,
+    changes: Vec<Change>,
+    before: Cursor,
+    after: Cursor,
+}
+
+// Lines `start..start + old` were replaced by `new` lines, for whatever
+// has to follow the text around.
+#[derive(Clone, Copy)]
+pub struct Edit {
+    pub start: usize,
+    pub old: usize,
+    pub new: usize,
+}
+
+impl Edit {
+    // Lines both texts start or end with are left out, so that a line
+    // pushed down by a new one above it counts as moved.
+    fn new(start: usize, old: &[String], new: &[String]) -> Self {
+        let same = |(a, b): (&String, &String)| a == b;
+        let prefix = old.iter().zip(new).take_while(|&pair| same(pair)).count();
+        let (old, new) = (&old[prefix..], &new[prefix..]);
+        let suffix = old
+            .iter()
+            .rev()
+            .zip(new.iter().rev())
+            .take_while(|&pair| same(pair))
+            .count();
+        Edit {
+            start: start + prefix,
+            old: old.len() - suffix,
+            new: new.len() - suffix,
+        }
+    }
+}
+
+// A change that has been started but whose new lines are not known yet:
+// the start, the old lines and the line count before the edit.
+struct Pending {
+    start: usize,
+    old: Vec<String>,
+    total: usize,
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    pending: Option<Pending>,
+    // Whether the last step may still take more edits of its kind.
+    open: bool,
+    // Line edits since the last `take_edits`, undo and redo included.
+    edits: Vec<Edit>,
+    // The cursor edits are recorded at while several cursors edit at once,
+    // and whether the group has its step yet.
+    group: Option<(Cursor, bool)>,
+}
+
+impl History {
+    // To be called before lines `rows` are replaced.
+    pub fn record(&mut self, lines: &[String], rows: Range<usize>, cursor: Cursor, kind: EditKind) {
+        self.finish(lines);
+        self.redo.clear();
+        let (cursor, grouped) = self.group.unwrap_or((cursor, false));
+        let extends = self.open
+            && kind != EditKind::Other
+            && self
+                .undo
+                .last()
+                .is_some_and(|step| step.kind == kind && step.after == cursor);
+        if let Some((_, grouped)) = &mut self.group {
+            *grouped = true;
+        }
+        if !grouped && !extends {
+            self.undo.push(Step {
+                kind,
+                changes: Vec::new(),
+                before: cursor,
+                after: cursor,
+            });
+        }
+        self.open = true;
+        self.pending = Some(Pending {
+            start: rows.start,
+            old: lines[rows].to_vec(),
+            total: lines.len(),
+        });
+    }
+
+    // Takes the new text of the change in progress.
+    fn finish(&mut self, lines: &[String]) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        let count = pending.old.len() + lines.len() - pending.total;
+        let new = lines[pending.start..pending.start + count].to_vec();
+        self.edits
+            .push(Edit::new(pending.start, &pending.old, &new));
+        if let Some(step) = self.undo.last_mut() {
+            step.changes.push(Change {
+                start: pending.start,
+                old: pending.old,
+                new,
+            });
+        }
+    }
+
+    // Makes the edits until `end_group` a single step, as if they were all
+    // made at `cursor`.
+    pub fn start_group(&mut self, cursor: Cursor) {
+        self.group = Some((cursor, false));
+    }
+
+    pub fn end_group(&mut self) {
+        self.group = None;
+    }
+
+    // To be called after every command. Moving the cursor elsewhere ends
+    // the step.
+    pub fn commit(&mut self, lines: &[String], cursor: Cursor) {
+        if self.pending.is_some() {
+            self.finish(lines);
+            if let Some(step) = self.undo.last_mut() {
+                step.after = cursor;
+            }
+        } else if self.undo.last().is_some_and(|step| step.after != cursor) {
+            self.open = false;
+        }
+    }
+
+    pub fn undo(&mut self, lines: &mut Vec<String>) -> Option<Cursor> {
+        self.finish(lines);
+        self.open = false;
+        let step = self.undo.pop()?;
+        for change in step.changes.iter().rev() {
+            let end = change.start + change.new.len();
+            lines.splice(change.start..end, change.old.iter().cloned());
+            self.edits
+                .push(Edit::new(change.start, &change.new, &change.old));
+        }
+        let cursor = step.before;
+        self.redo.push(step);
+        Some(cursor)
+    }
+
+    pub fn redo(&mut self, lines: &mut Vec<String>) -> Option<Cursor> {
+        let step = self.redo.pop()?;
+        for change in &step.changes {
+            let end = change.start + change.old.len();
+            lines.splice(change.start..end, change.new.iter().cloned());
+            self.edits
+                .push(Edit::new(change.start, &change.old, &change.new));
+        }
+        let cursor = step.after;
+        self.undo.push(step);
+        Some(cursor)
+    }
+
+    pub fn take_edits(&mut self) -> Vec<Edit> {
+        std::mem::take(&mut self.edits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    // Types `c` at the end of line 0, as the editor does.
+    fn type_char(history: &mut History, text: &mut [String], c: char) {
+        let x = text[0].len();
+        history.record(text, 0..1, (0, x), EditKind::Insert);
+        text[0].push(c);
+        history.commit(text, (0, x + 1));
+    }
+
+    #[test]
+    fn typing_is_undone_at_once() {
+        let mut history = History::default();
+        let mut text = lines(&["a"]);
+        for c in "bcd".chars() {
+            type_char(&mut history, &mut text, c);
+        }
+        assert_eq!(history.undo(&mut text), Some((0, 1)));
+        assert_eq!(text, ["a"]);
+        assert_eq!(history.undo(&mut text), None);
+    }
+
+    #[test]
+    fn moving_the_cursor_starts_a_new_step() {
+        let mut history = History::default();
+        let mut text = lines(&["a"]);
+        type_char(&mut history, &mut text, 'b');
+        history.commit(&text, (0, 0));
+        history.commit(&text, (0, 2));
+        type_char(&mut history, &mut text, 'c');
+        history.undo(&mut text);
+        assert_eq!(text, ["ab"]);
+    }
+
+    #[test]
+    fn other_edits_are_steps_of_their_own() {
+        let mut history = History::default();
+        let mut text = lines(&["a", "b"]);
+        for _ in 0..2 {
+            history.record(&text, 0..2, (0, 0), EditKind::Other);
+            text.insert(0, String::new());
+            history.commit(&text, (0, 0));
+        }
+        history.undo(&mut text);
+        assert_eq!(text, ["", "a", "b"]);
+    }
+
+    #[test]
+    fn redo_puts_back_what_undo_took() {
+        let mut history = History::default();
+        let mut text = lines(&["a", "b"]);
+        history.record(&text, 0..2, (1, 0), EditKind::Delete);
+        text.splice(0..2, lines(&["ab"]));
+        history.commit(&text, (0, 1));
+
+        assert_eq!(history.undo(&mut text), Some((1, 0)));
+        assert_eq!(text, ["a", "b"]);
+        assert_eq!(history.redo(&mut text), Some((0, 1)));
+        assert_eq!(text, ["ab"]);
+        assert_eq!(history.redo(&mut text), None);
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_redo_steps() {
+        let mut history = History::default();
+        let mut text = lines(&["a"]);
+        type_char(&mut history, &mut text, 'b');
+        history.undo(&mut text);
+        type_char(&mut history, &mut text, 'c');
+        assert_eq!(history.redo(&mut text), None);
+        assert_eq!(text, ["ac"]);
+    }
+
+    fn edit(old: &[&str], new: &[&str]) -> (usize, usize, usize) {
+        let edit = Edit::new(10, &lines(old), &lines(new));
+        (edit.start, edit.old, edit.new)
+    }
+
+    #[test]
+    fn edits_leave_out_the_lines_that_stay_the_same() {
+        assert_eq!(edit(&["a", "b"], &["a", "x", "b"]), (11, 0, 1));
+        assert_eq!(edit(&["a", "b", "c"], &["a", "c"]), (11, 1, 0));
+        assert_eq!(edit(&["a", "b"], &["x", "y"]), (10, 2, 2));
+        assert_eq!(edit(&["a"], &["a"]), (11, 0, 0));
+    }
+
+    #[test]
+    fn undo_and_redo_report_their_edits() {
+        let mut history = History::default();
+        let mut text = lines(&["a"]);
+        history.record(&text, 0..1, (0, 1), EditKind::Other);
+        text.push("b".to_string());
+        history.commit(&text, (1, 0));
+        history.undo(&mut text);
+        history.redo(&mut text);
+        let edits: Vec<_> = history
+            .take_edits()
+            .iter()
+            .map(|edit| (edit.start, edit.old, edit.new))
+            .collect();
+        assert_eq!(edits, [(1, 0, 1), (1, 1, 0), (1, 0, 1)]);
+        assert!(history.take_edits().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::config::Settings;
+use crate::text;
+
+// Line endings after which the next line goes one level deeper, by
+// lower-case syntax name. Other syntaxes only indent after an open bracket.
+const OPENERS: &[(&str, &[&str])] = &[
+    ("rust", &["{", "(", "[", "=>"]),
+    ("python", &[":", "(", "[", "{"]),
+    ("yaml", &[":"]),
+    ("lua", &["then", "do", "{", "("]),
+    ("ruby", &["do", "{", "(", "["]),
+    ("bourne again shell (bash)", &["then", "do", "{", "("]),
+];
+
+const BRACKETS: &[&str] = &["{", "(", "["];
+
+// Leading spaces and tabs of `line`.
+pub fn leading(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+// One level of indentation.
+pub fn unit(settings: &Settings) -> String {
+    if settings.expand_tabs {
+        " ".repeat(settings.indent_size)
+    } else {
+        "\t".to_string()
+    }
+}
+
+// Whether a line ending with `text` opens a deeper level.
+pub fn opens(syntax: &str, text: &str) -> bool {
+    let syntax = syntax.to_lowercase();
+    let openers = OPENERS
+        .iter()
+        .find(|(name, _)| *name == syntax)
+        .map_or(BRACKETS, |(_, openers)| openers);
+    let text = text.trim_end();
+    openers.iter().any(|opener| {
+        text.strip_suffix(opener).is_some_and(|rest| {
+            // Whole words only: `do` but not `undo`.
+            !opener.ends_with(char::is_alphanumeric)
+                || !rest.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+        })
+    })
+}
+
+pub fn is_closer(c: char) -> bool {
+    matches!(c, '}' | ')' | ']')
+}
+
+// `indentation` with its last level taken off.
+pub fn dedent(indentation: &str, settings: &Settings) -> String {
+    let mut indentation = indentation.to_string();
+    if indentation.ends_with('\t') {
+        indentation.pop();
+    } else {
+        let spaces = indentation.len() - indentation.trim_end_matches(' ').len();
+        let remove = match spaces % settings.indent_size {
+            0 => settings.indent_size.min(spaces),
+            partial => partial,
+        };
+        indentation.truncate(indentation.len() - remove);
+    }
+    indentation
+}
+
+// `indentation` rewritten with tabs or spaces as the settings say, keeping
+// its width.
+pub fn retab(indentation: &str, settings: &Settings) -> String {
+    let tab_width = settings.tab_width;
+    let width = text::display_col(indentation, indentation.chars().count(), tab_width);
+    if settings.expand_tabs {
+        " ".repeat(width)
+    } else {
+        "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(expand_tabs: bool) -> Settings {
+        Settings {
+            expand_tabs,
+            indent_size: 4,
+            tab_width: 4,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn openers_depend_on_the_syntax() {
+        assert!(opens("Python", "if x:  "));
+        assert!(!opens("Rust", "if x:"));
+        assert!(opens("Rust", "match x {"));
+        assert!(opens("Plain Text", "call("));
+        assert!(!opens("Plain Text", "x =>"));
+    }
+
+    #[test]
+    fn keywords_open_only_as_whole_words() {
+        assert!(opens("Lua", "for i = 1, 3 do"));
+        assert!(!opens("Lua", "undo"));
+        assert!(!opens("Lua", "my_do"));
+    }
+
+    #[test]
+    fn a_level_is_a_tab_or_spaces() {
+        assert_eq!(leading("  \t x"), "  \t ");
+        assert_eq!(unit(&settings(false)), "\t");
+        assert_eq!(unit(&settings(true)), "    ");
+    }
+
+    #[test]
+    fn dedent_removes_one_level_or_the_partial_one() {
+        let settings = settings(true);
+        assert_eq!(dedent("\t\t", &settings), "\t");
+        assert_eq!(dedent("        ", &settings), "    ");
+        assert_eq!(dedent("      ", &settings), "    ");
+        assert_eq!(dedent("  ", &settings), "");
+        assert_eq!(dedent("", &settings), "");
+    }
+
+    #[test]
+    fn retab_keeps_the_width() {
+        assert_eq!(retab("\t  ", &settings(true)), "      ");
+        assert_eq!(retab("      ", &settings(false)), "\t  ");
+        assert_eq!(retab("  \t", &settings(false)), "\t");
+    }
+}
@@ -0,0 +1,128 @@
+// Positions to go back to after a large jump, like Vim's jump list.
+
+const MAX_JUMPS: usize = 100;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Jump {
+    pub filename: String,
+    pub y: usize,
+    pub x: usize,
+}
+
+#[derive(Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    // Where going back and forth has got to, `jumps.len()` when it hasn't
+    // started.
+    index: usize,
+}
+
+impl JumpList {
+    // Remembers `from` before jumping away from it. Positions ahead of the
+    // current one are forgotten.
+    pub fn push(&mut self, from: Jump) {
+        self.jumps.truncate(self.index);
+        if self.jumps.last() != Some(&from) {
+            self.jumps.push(from);
+        }
+        self.index = self.jumps.len();
+        self.trim();
+    }
+
+    // The position before the current one. Going back from the newest
+    // position remembers it, so going forward again returns there.
+    pub fn back(&mut self, current: Jump) -> Option<Jump> {
+        let newest = self.index == self.jumps.len();
+        let remember = newest && self.jumps.last() != Some(&current);
+        let mut index = self.index;
+        if newest && !remember {
+            index -= 1;
+        }
+        let index = index.checked_sub(1)?;
+        if remember {
+            self.jumps.push(current);
+        }
+        self.index = index;
+        self.trim();
+        Some(self.jumps[self.index].clone())
+    }
+
+    // Forgets the oldest positions past `MAX_JUMPS`.
+    fn trim(&mut self) {
+        let excess = self.jumps.len().saturating_sub(MAX_JUMPS);
+        self.jumps.drain(..excess);
+        self.index = self.index.saturating_sub(excess);
+    }
+
+    pub fn forward(&mut self) -> Option<Jump> {
+        if self.index + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.jumps[self.index].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(y: usize) -> Jump {
+        Jump {
+            filename: "a.rs".to_string(),
+            y,
+            x: 0,
+        }
+    }
+
+    fn row(jump: Option<Jump>) -> Option<usize> {
+        jump.map(|jump| jump.y)
+    }
+
+    #[test]
+    fn back_and_forward_return_to_where_the_jumps_started() {
+        let mut jumps = JumpList::default();
+        jumps.push(at(1));
+        jumps.push(at(2));
+        assert_eq!(row(jumps.back(at(3))), Some(2));
+        assert_eq!(row(jumps.back(at(2))), Some(1));
+        assert_eq!(row(jumps.back(at(1))), None);
+        assert_eq!(row(jumps.forward()), Some(2));
+        assert_eq!(row(jumps.forward()), Some(3));
+        assert_eq!(row(jumps.forward()), None);
+    }
+
+    #[test]
+    fn going_back_from_the_newest_position_skips_it() {
+        let mut jumps = JumpList::default();
+        jumps.push(at(1));
+        jumps.push(at(2));
+        assert_eq!(row(jumps.back(at(2))), Some(1));
+    }
+
+    #[test]
+    fn a_new_jump_forgets_the_positions_ahead() {
+        let mut jumps = JumpList::default();
+        jumps.push(at(1));
+        jumps.push(at(2));
+        jumps.back(at(3));
+        jumps.back(at(2));
+        jumps.push(at(1));
+        assert_eq!(row(jumps.forward()), None);
+        assert_eq!(row(jumps.back(at(5))), Some(1));
+    }
+
+    #[test]
+    fn only_the_newest_positions_are_kept() {
+        let mut jumps = JumpList::default();
+        for y in 0..MAX_JUMPS + 10 {
+            jumps.push(at(y));
+        }
+        let mut oldest = None;
+        while let Some(jump) = jumps.back(at(1000)) {
+            oldest = Some(jump.y);
+        }
+        // Going back remembered the current position as well.
+        assert_eq!(oldest, Some(11));
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use termion::event::{Event, Key};
+
+use crate::commands::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    F(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub code: KeyCode,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+impl Chord {
+    pub fn plain(code: KeyCode) -> Self {
+        Chord {
+            code,
+            ctrl: false,
+            alt: false,
+            shift: false,
+        }
+    }
+
+    fn with(code: KeyCode, ctrl: bool, alt: bool, shift: bool) -> Self {
+        Chord {
+            code,
+            ctrl,
+            alt,
+            shift,
+        }
+        .normalized()
+    }
+
+    // Shifted letters are reported as upper case characters, so "shift+a"
+    // and "A" must end up being the same chord.
+    fn normalized(mut self) -> Self {
+        if let KeyCode::Char(c) = self.code {
+            if self.ctrl {
+                self.code = KeyCode::Char(c.to_ascii_lowercase());
+            } else if self.shift && c.is_alphabetic() {
+                self.code = KeyCode::Char(c.to_uppercase().next().unwrap_or(c));
+                self.shift = false;
+            }
+        }
+        self
+    }
+
+    pub fn from_event(event: &Event) -> Option<Self> {
+        let key = match event {
+            Event::Key(key) => *key,
+            Event::Unsupported(bytes) => return Self::from_csi(bytes),
+            Event::Mouse(_) => return None,
+        };
+
+        let chord = |code| Chord::plain(code);
+        let ctrl = |code| Chord::with(code, true, false, false);
+        let alt = |code| Chord::with(code, false, true, false);
+        let shift = |code| Chord::with(code, false, false, true);
+
+        Some(match key {
+            Key::Char('\n') => chord(KeyCode::Enter),
+            Key::Char('\t') => chord(KeyCode::Tab),
+            Key::Char(c) => chord(KeyCode::Char(c)),
+            // Ctrl+/ sends 0x1f, which termion reads as Ctrl+7, and
+            // Ctrl+Backspace sends 0x08, the same as Ctrl+H.
+            Key::Ctrl('7') => ctrl(KeyCode::Char('/')),
+            Key::Ctrl('h') => ctrl(KeyCode::Backspace),
+            Key::Ctrl(c) => ctrl(KeyCode::Char(c)),
+            Key::Alt('\n') | Key::Alt('\r') => alt(KeyCode::Enter),
+            Key::Alt('\x7f') => alt(KeyCode::Backspace),
+            Key::Alt(c) => alt(KeyCode::Char(c)),
+            Key::Null => ctrl(KeyCode::Char(' ')),
+            Key::Backspace => chord(KeyCode::Backspace),
+            Key::Delete => chord(KeyCode::Delete),
+            Key::Insert => chord(KeyCode::Insert),
+            Key::Esc => chord(KeyCode::Esc),
+            Key::Up => chord(KeyCode::Up),
+            Key::Down => chord(KeyCode::Down),
+            Key::Left => chord(KeyCode::Left),
+            Key::Right => chord(KeyCode::Right),
+            Key::ShiftUp => shift(KeyCode::Up),
+            Key::ShiftDown => shift(KeyCode::Down),
+            Key::ShiftLeft => shift(KeyCode::Left),
+            Key::ShiftRight => shift(KeyCode::Right),
+            Key::AltUp => alt(KeyCode::Up),
+            Key::AltDown => alt(KeyCode::Down),
+            Key::AltLeft => alt(KeyCode::Left),
+            Key::AltRight => alt(KeyCode::Right),
+            Key::CtrlUp => ctrl(KeyCode::Up),
+            Key::CtrlDown => ctrl(KeyCode::Down),
+            Key::CtrlLeft => ctrl(KeyCode::Left),
+            Key::CtrlRight => ctrl(KeyCode::Right),
+            Key::Home => chord(KeyCode::Home),
+            Key::End => chord(KeyCode::End),
+            Key::CtrlHome => ctrl(KeyCode::Home),
+            Key::CtrlEnd => ctrl(KeyCode::End),
+            Key::PageUp => chord(KeyCode::PageUp),
+            Key::PageDown => chord(KeyCode::PageDown),
+            Key::BackTab => shift(KeyCode::Tab),
+            Key::F(n) => chord(KeyCode::F(n)),
+            _ => return None,
+        })
+    }
+
+    // termion gives up on CSI sequences with modifier combinations it does
+    // not know, e.g. `ESC [ 1 ; 4 A` (alt+shift+up) or `ESC [ 3 ; 5 ~`
+    // (ctrl+delete).
+    fn from_csi(bytes: &[u8]) -> Option<Self> {
+        let body = std::str::from_utf8(bytes.strip_prefix(b"\x1b[")?).ok()?;
+        let last = body.chars().last()?;
+        let params: Vec<u8> = body[..body.len() - 1]
+            .split(';')
+            .map(|n| n.parse().ok())
+            .collect::<Option<_>>()?;
+        let [number, modifiers] = params[..] else {
+            return None;
+        };
+
+        let code = match (last, number) {
+            ('A', 1) => KeyCode::Up,
+            ('B', 1) => KeyCode::Down,
+            ('C', 1) => KeyCode::Right,
+            ('D', 1) => KeyCode::Left,
+            ('H', 1) | ('~', 1) | ('~', 7) => KeyCode::Home,
+            ('F', 1) | ('~', 4) | ('~', 8) => KeyCode::End,
+            ('~', 2) => KeyCode::Insert,
+            ('~', 3) => KeyCode::Delete,
+            ('~', 5) => KeyCode::PageUp,
+            ('~', 6) => KeyCode::PageDown,
+            _ => return None,
+        };
+        let bits = modifiers.checked_sub(1)?;
+        Some(Chord::with(code, bits & 4 != 0, bits & 2 != 0, bits & 1 != 0))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text == "+" {
+            return Ok(Chord::plain(KeyCode::Char('+')));
+        }
+        let parts: Vec<&str> = text.split('+').collect();
+        let (name, modifiers) = match parts.split_last() {
+            // "ctrl++" binds the plus key itself
+            Some((&"", rest)) if text.ends_with("++") => ("+", &rest[..rest.len() - 1]),
+            Some((name, rest)) => (*name, rest),
+            None => return Err(format!("empty key `{}`", text)),
+        };
+
+        let (mut ctrl, mut alt, mut shift) = (false, false, false);
+        for modifier in modifiers {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "c" => ctrl = true,
+                "alt" | "meta" | "m" => alt = true,
+                "shift" | "s" => shift = true,
+                _ => return Err(format!("unknown modifier `{}` in `{}`", modifier, text)),
+            }
+        }
+
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match name.to_ascii_lowercase().as_str() {
+                "enter" | "return" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" | "ins" => KeyCode::Insert,
+                "esc" | "escape" => KeyCode::Esc,
+                "space" => KeyCode::Char(' '),
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                f => match f.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key `{}`", text)),
+                },
+            },
+        };
+        Ok(Chord::with(code, ctrl, alt, shift))
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "ctrl+")?;
+        }
+        if self.alt {
+            write!(f, "alt+")?;
+        }
+        if self.shift {
+            write!(f, "shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => write!(f, "{}", format!("{:?}", code).to_lowercase()),
+        }
+    }
+}
+
+pub fn parse_sequence(text: &str) -> Result<Vec<Chord>, String> {
+    let sequence = text
+        .split_whitespace()
+        .map(Chord::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if sequence.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    Ok(sequence)
+}
+
+pub fn format_sequence(sequence: &[Chord]) -> String {
+    sequence
+        .iter()
+        .map(|chord| chord.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub enum Lookup {
+    Command(Command),
+    Pending,
+    Unbound(Vec<Chord>),
+}
+
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: HashMap<Vec<Chord>, Command>,
+    pending: Vec<Chord>,
+}
+
+impl Keymap {
+    fn from_defaults(defaults: &[(&str, Command)]) -> Self {
+        let mut keymap = Keymap {
+            bindings: HashMap::new(),
+            pending: Vec::new(),
+        };
+        for (keys, command) in defaults {
+            keymap.bind(parse_sequence(keys).unwrap(), *command);
+        }
+        keymap
+    }
+
+    pub fn editor() -> Self {
+        Keymap::from_defaults(&[
+            ("ctrl+q", Command::Quit),
+            ("ctrl+s", Command::Save),
+            ("ctrl+w", Command::SaveAndQuit),
+            ("alt+s", Command::SaveAs),
+            ("ctrl+f", Command::Search),
+            ("alt+m", Command::ShowMessages),
+            ("esc", Command::DismissMessage),
+            ("alt+x", Command::Execute),
+            ("up", Command::MoveUp),
+            ("down", Command::MoveDown),
+            ("left", Command::MoveLeft),
+            ("right", Command::MoveRight),
+            ("enter", Command::InsertNewline),
+            ("backspace", Command::DeleteBackward),
+            ("alt+]", Command::MatchBracket),
+            ("shift+up", Command::SelectUp),
+            ("shift+down", Command::SelectDown),
+            ("shift+left", Command::SelectLeft),
+            ("shift+right", Command::SelectRight),
+            ("tab", Command::Indent),
+            ("shift+tab", Command::Dedent),
+            ("ctrl+z", Command::Undo),
+            ("ctrl+y", Command::Redo),
+            ("ctrl+/", Command::ToggleComment),
+            ("ctrl+left", Command::MoveWordLeft),
+            ("ctrl+right", Command::MoveWordRight),
+            ("ctrl+backspace", Command::DeleteWordBackward),
+            ("alt+backspace", Command::DeleteWordBackward),
+            ("ctrl+delete", Command::DeleteWordForward),
+            ("delete", Command::DeleteForward),
+            ("home", Command::MoveHome),
+            ("end", Command::MoveEnd),
+            ("pageup", Command::PageUp),
+            ("pagedown", Command::PageDown),
+            ("ctrl+home", Command::MoveFileStart),
+            ("ctrl+end", Command::MoveFileEnd),
+            ("shift+home", Command::SelectHome),
+            ("shift+end", Command::SelectEnd),
+            ("ctrl+g", Command::GoToLine),
+            ("ctrl+o", Command::OpenFile),
+            ("alt+left", Command::JumpBack),
+            ("alt+right", Command::JumpForward),
+            ("alt+k", Command::SetMark),
+            ("alt+'", Command::JumpToMark),
+            ("ctrl+alt+up", Command::AddCursorAbove),
+            ("ctrl+alt+down", Command::AddCursorBelow),
+            ("ctrl+d", Command::AddNextOccurrence),
+            ("alt+shift+up", Command::SelectBlockUp),
+            ("alt+shift+down", Command::SelectBlockDown),
+            ("alt+shift+left", Command::SelectBlockLeft),
+            ("alt+shift+right", Command::SelectBlockRight),
+            ("ctrl+c", Command::Copy),
+            ("ctrl+x", Command::Cut),
+            ("ctrl+v", Command::Paste),
+        ])
+    }
+
+    pub fn explorer() -> Self {
+        Keymap::from_defaults(&[
+            ("q", Command::Quit),
+            ("ctrl+q", Command::Quit),
+            ("alt+m", Command::ShowMessages),
+            ("alt+x", Command::Execute),
+            ("up", Command::MoveUp),
+            ("down", Command::MoveDown),
+            ("enter", Command::Open),
+            ("ctrl+d", Command::ParentDir),
+            ("ctrl+g", Command::GoTo),
+        ])
+    }
+
+    pub fn bind(&mut self, sequence: Vec<Chord>, command: Command) {
+        self.bindings.insert(sequence, command);
+    }
+
+    pub fn unbind(&mut self, sequence: &[Chord]) {
+        self.bindings.remove(sequence);
+    }
+
+    pub fn pending(&self) -> &[Chord] {
+        &self.pending
+    }
+
+    pub fn lookup(&mut self, chord: Chord) -> Lookup {
+        self.pending.push(chord);
+
+        if let Some(command) = self.bindings.get(&self.pending) {
+            self.pending.clear();
+            return Lookup::Command(*command);
+        }
+
+        let is_prefix = self
+            .bindings
+            .keys()
+            .any(|keys| keys.len() > self.pending.len() && keys.starts_with(&self.pending));
+        if is_prefix {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound(std::mem::take(&mut self.pending))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(text: &str) -> Chord {
+        Chord::parse(text).unwrap()
+    }
+
+    #[test]
+    fn modifiers_and_names_are_parsed() {
+        let save = chord("Ctrl+S");
+        assert_eq!(save, Chord::with(KeyCode::Char('s'), true, false, false));
+        assert_eq!(
+            chord("m+pagedown"),
+            Chord::with(KeyCode::PageDown, false, true, false)
+        );
+        assert_eq!(chord("f12"), Chord::plain(KeyCode::F(12)));
+        assert_eq!(chord("space"), Chord::plain(KeyCode::Char(' ')));
+    }
+
+    #[test]
+    fn the_plus_key_can_be_bound() {
+        assert_eq!(chord("+"), Chord::plain(KeyCode::Char('+')));
+        assert_eq!(
+            chord("ctrl++"),
+            Chord::with(KeyCode::Char('+'), true, false, false)
+        );
+    }
+
+    #[test]
+    fn shifted_letters_are_upper_case() {
+        assert_eq!(chord("shift+a"), chord("A"));
+        assert_eq!(chord("ctrl+A"), chord("ctrl+a"));
+    }
+
+    #[test]
+    fn bad_keys_are_reported() {
+        assert_eq!(
+            Chord::parse("hyper+a").unwrap_err(),
+            "unknown modifier `hyper` in `hyper+a`"
+        );
+        assert_eq!(Chord::parse("f13").unwrap_err(), "unknown key `f13`");
+        assert!(parse_sequence("  ").is_err());
+    }
+
+    #[test]
+    fn sequences_are_formatted_back() {
+        let text = "ctrl+x alt+shift+up space f5";
+        assert_eq!(format_sequence(&parse_sequence(text).unwrap()), text);
+    }
+
+    #[test]
+    fn termion_keys_become_chords() {
+        let event = |key| Chord::from_event(&Event::Key(key));
+        assert_eq!(event(Key::Ctrl('7')), Some(chord("ctrl+/")));
+        assert_eq!(event(Key::Ctrl('h')), Some(chord("ctrl+backspace")));
+        assert_eq!(event(Key::BackTab), Some(chord("shift+tab")));
+        assert_eq!(event(Key::Char('\n')), Some(chord("enter")));
+    }
+
+    #[test]
+    fn modified_csi_sequences_are_read() {
+        let csi = |bytes: &[u8]| Chord::from_event(&Event::Unsupported(bytes.to_vec()));
+        assert_eq!(csi(b"\x1b[1;4A"), Some(chord("alt+shift+up")));
+        assert_eq!(csi(b"\x1b[3;5~"), Some(chord("ctrl+delete")));
+        assert_eq!(csi(b"\x1b[9;5~"), None);
+    }
+
+    #[test]
+    fn sequences_wait_for_their_next_key() {
+        let mut keymap = Keymap::editor();
+        keymap.bind(
+            parse_sequence("ctrl+k ctrl+c").unwrap(),
+            Command::ToggleComment,
+        );
+        assert!(matches!(keymap.lookup(chord("ctrl+k")), Lookup::Pending));
+        assert_eq!(keymap.pending(), [chord("ctrl+k")]);
+        assert!(matches!(
+            keymap.lookup(chord("ctrl+c")),
+            Lookup::Command(Command::ToggleComment)
+        ));
+        assert!(keymap.pending().is_empty());
+    }
+
+    #[test]
+    fn unbound_keys_are_given_back() {
+        let mut keymap = Keymap::editor();
+        keymap.unbind(&[chord("ctrl+s")]);
+        match keymap.lookup(chord("ctrl+s")) {
+            Lookup::Unbound(keys) => assert_eq!(keys, [chord("ctrl+s")]),
+            _ => panic!("ctrl+s is still bound"),
+        }
+    }
+}
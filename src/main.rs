@@ -1,45 +1,274 @@
+mod block;
+mod brackets;
+mod colors;
+mod commands;
+mod comment;
+mod config;
+mod editorconfig;
+mod fileformat;
 mod filemanager;
 mod highlight;
+mod history;
+mod indent;
+mod jumps;
+mod keymap;
+mod marks;
+mod message;
+mod pairs;
+mod picker;
+mod prompt;
+mod statusline;
+mod terminal;
+mod text;
+mod theme;
+mod treesitter;
 
 use std::env;
-use std::fs::File;
-use std::io::{self, BufWriter, Write, stdout};
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::ops::Range;
 use std::path::Path;
+use std::time::Duration;
 
-use termion::color::{self, Yellow};
+use termion::color;
+use syntect::highlighting::{self, FontStyle, Style};
 use termion::event::{Event,Key,MouseEvent,MouseButton};
-use termion::input::{TermRead,MouseTerminal};
 use termion::{clear, cursor, style};
-use termion::raw::IntoRawMode;
+
+use block::Block;
+use commands::Command;
+use config::{Config, Settings};
+use editorconfig::FileStyle;
+use highlight::{Highlighter, Kind, Span};
+use history::{EditKind, History};
+use jumps::{Jump, JumpList};
+use fileformat::{Encoding, LineEnding};
+use keymap::{Chord, KeyCode, Keymap, Lookup};
+use marks::Marks;
+use message::Messages;
+use prompt::Prompt;
+use statusline::{StatusInfo, StatusLine};
+use terminal::{TermEvent, Terminal};
+use theme::Palette;
+
+// How a character stands out from its syntax colours.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    None,
+    Selected,
+    Bracket,
+}
+
+// Where a cursor is, with the parts of `Editor` that go with it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Cursor {
+    y: usize,
+    x: usize,
+    anchor: Option<(usize, usize)>,
+    goal_col: Option<(usize, (usize, usize))>,
+}
+
+impl Cursor {
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.anchor?;
+        let at = (self.y, self.x);
+        (anchor != at).then(|| (anchor.min(at), anchor.max(at)))
+    }
+
+    // Lines touched by the selection, or the cursor line. A selection that
+    // ends at the start of a line leaves that line out.
+    fn rows(&self) -> Range<usize> {
+        match self.selection() {
+            Some(((y1, _), (y2, 0))) if y2 > y1 => y1..y2,
+            Some(((y1, _), (y2, _))) => y1..y2 + 1,
+            None => self.y..self.y + 1,
+        }
+    }
+}
+
+// Text copied or cut, kept for the whole session.
+#[derive(Clone)]
+enum Clipboard {
+    // The lines of a selection, the first and the last joining the lines
+    // they are pasted into.
+    Text(Vec<String>),
+    // What a block selection had on each line, pasted as a column.
+    Block(Vec<String>),
+}
 
 struct Editor {
+    filename: String,
     lines: Vec<String>,
     cursor_x: usize,
     cursor_y: usize,
+    // The other end of the selection, as (line, char index).
+    anchor: Option<(usize, usize)>,
+    // More cursors editing along with the main one, in file order.
+    cursors: Vec<Cursor>,
+    // A block selection instead of `anchor`. The cursor is kept at its
+    // cursor corner.
+    block: Option<Block>,
+    // Kept when another file is opened in place of this one.
+    clipboard: Option<Clipboard>,
+    history: History,
+    // The display column for vertical moves and where the cursor was left
+    // by the last of them. Any other move makes it stale.
+    goal_col: Option<(usize, (usize, usize))>,
+    // Kept when another file is opened in place of this one.
+    jumps: JumpList,
+    // Closing characters typed by auto-pairing, as (line, char index).
+    // Typing the same character there steps over it.
+    closers: Vec<(usize, usize)>,
+    marks: Marks,
     row_offset: usize,
     col_offset: usize,
     is_changed: bool,
-    highlighter: highlight::Highlighter,
+    highlighter: Highlighter,
+    screen_width: u16,
+    screen_height: u16,
+    read_only: bool,
+    // The file is there but couldn't be read, so the buffer must not be
+    // written over it.
+    unread: bool,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    statusline: StatusLine,
+    messages: Messages,
+    keymap: Keymap,
+    config: Config,
+    // `config.settings` with the `.editorconfig` properties applied.
+    settings: Settings,
+    file_style: FileStyle,
+    palette: Palette,
 }
 
 impl Editor {
-    fn new(filename: &str) -> Self {
+    fn new(filename: &str, config: &Config) -> Self {
+        let mut messages = Messages::default();
+        let file_style = FileStyle::resolve(Path::new(filename)).unwrap_or_else(|err| {
+            messages.warning(format!(".editorconfig: {}", err));
+            FileStyle::default()
+        });
+        let mut settings = config.settings.clone();
+        file_style.apply(&mut settings);
+        messages.set_timeout(Duration::from_secs(settings.message_timeout));
+        let theme = theme::load_or_default(&settings.theme);
+        colors::set_mode(settings.colors);
+
         Editor {
+            filename: filename.to_string(),
             lines: vec![String::new()],
             cursor_x: 0,
             cursor_y: 0,
+            anchor: None,
+            cursors: Vec::new(),
+            block: None,
+            clipboard: None,
+            history: History::default(),
+            goal_col: None,
+            jumps: JumpList::default(),
+            closers: Vec::new(),
+            marks: Marks::default(),
             row_offset: 0,
             col_offset: 0,
             is_changed: false,
-            highlighter: highlight::Highlighter::new(filename),
+            palette: Palette::new(&theme, settings.background),
+            highlighter: Highlighter::new(theme, settings.highlighter.clone()),
+            screen_width: 80,
+            screen_height: 24,
+            read_only: false,
+            unread: false,
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            statusline: StatusLine::parse(&settings.statusline).unwrap_or_default(),
+            messages,
+            keymap: config.editor_keys.clone(),
+            config: config.clone(),
+            settings,
+            file_style,
         }
     }
 
-    fn scroll(&mut self) {
-        let (width, height) = termion::terminal_size().unwrap();
+    fn apply_config(&mut self) {
+        self.settings = self.config.settings.clone();
+        self.file_style.apply(&mut self.settings);
+        let theme = theme::load_or_default(&self.settings.theme);
+        colors::set_mode(self.settings.colors);
+        self.palette = Palette::new(&theme, self.settings.background);
+        self.highlighter.set_theme(theme);
+        self.highlighter.set_backends(self.settings.highlighter.clone());
+        self.statusline = StatusLine::parse(&self.settings.statusline).unwrap_or_default();
+        self.messages
+            .set_timeout(Duration::from_secs(self.settings.message_timeout));
+        self.keymap = self.config.editor_keys.clone();
+    }
+
+    // Re-reads the configuration files and the `.editorconfig` properties
+    // for the current file name, returns false when some of them had errors.
+    fn load_config(&mut self) -> bool {
+        let path = Path::new(&self.filename);
+        self.config = self.config.reload(path);
+        match FileStyle::resolve(path) {
+            Ok(style) => self.file_style = style,
+            Err(err) => self.config.errors.push(format!(".editorconfig: {}", err)),
+        }
+        self.apply_config();
+
+        let clean = self.config.errors.is_empty();
+        for error in self.config.errors.drain(..) {
+            self.messages.error(error);
+        }
+        clean
+    }
+
+    fn reload_config(&mut self) {
+        if self.load_config() {
+            self.messages.info("Configuration reloaded");
+        }
+    }
+
+    fn resize(&mut self, (width, height): (u16, u16)) {
+        self.screen_width = width.max(1);
+        self.screen_height = height.max(3);
+    }
 
-        let visible_height = (height - 1) as usize;
-        let visible_width = width as usize;
+    fn text_height(&self) -> usize {
+        (self.screen_height - 2) as usize
+    }
+
+    // Line numbers plus one column of padding.
+    fn gutter_width(&self) -> usize {
+        // The sign column with the marks only shows up once there are some.
+        let signs = if self.marks.is_empty() { 0 } else { 2 };
+        if self.settings.line_numbers {
+            signs + self.lines.len().to_string().len() + 1
+        } else {
+            signs
+        }
+    }
+
+    fn text_width(&self) -> usize {
+        (self.screen_width as usize)
+            .saturating_sub(self.gutter_width())
+            .max(1)
+    }
+
+    // In a block selection the cursor may be past the end of its line.
+    fn cursor_col(&self) -> usize {
+        if let Some(block) = self.block {
+            return block.cursor.1;
+        }
+        text::display_col(
+            &self.lines[self.cursor_y],
+            self.cursor_x,
+            self.settings.tab_width,
+        )
+    }
+
+    fn scroll(&mut self) {
+        let visible_height = self.text_height();
+        let visible_width = self.text_width();
+        let cursor_col = self.cursor_col();
 
         if self.cursor_y < self.row_offset {
             self.row_offset = self.cursor_y;
@@ -49,20 +278,19 @@ impl Editor {
             self.row_offset = self.cursor_y - visible_height + 1;
         }
 
-        if self.cursor_x < self.col_offset {
-            self.col_offset = self.cursor_x;
+        if cursor_col < self.col_offset {
+            self.col_offset = cursor_col;
         }
 
-        if self.cursor_x >= self.col_offset + visible_width {
-            self.col_offset = self.cursor_x - visible_width + 1;
+        if cursor_col >= self.col_offset + visible_width {
+            self.col_offset = cursor_col - visible_width + 1;
         }
     }
 
         
     fn scroll_for_mouse(&mut self,direction: i32, lines:usize) {
         let total = self.lines.len();
-        let (_, height) = termion::terminal_size().unwrap();
-        let visible_height = (height - 1) as usize;
+        let visible_height = self.text_height();
        
         let screen_row = self.cursor_y.saturating_sub(self.row_offset);
         
@@ -83,33 +311,609 @@ impl Editor {
     }
 
     fn mouse_click(&mut self, x:u16,y:u16){
+        if y as usize > self.text_height() {
+            return;
+        }
         let clicked_row = (y as usize -  1) + self.row_offset;
-        let cliked_col = (x as usize - 1) + self.col_offset;
+        let cliked_col = (x as usize - 1).saturating_sub(self.gutter_width()) + self.col_offset;
 
         if clicked_row < self.lines.len(){
+            self.anchor = None;
+            self.cursors.clear();
+            self.block = None;
             self.cursor_y = clicked_row;
-            let line_len = self.lines[self.cursor_y].chars().count();
-            self.cursor_x = cliked_col.min(line_len);
+            let tab_width = self.settings.tab_width;
+            self.cursor_x = text::char_at_col(&self.lines[self.cursor_y], cliked_col, tab_width);
 
         }
     }
 
 
 
-    fn insert_char(&mut self, c: char) {
-        let line = &mut self.lines[self.cursor_y];
-        let byte_ind = line
-            .char_indices()
-            .nth(self.cursor_x)
-            .map(|(i, _)| i)
-            .unwrap_or(line.len());
-        line.insert(byte_ind, c);
-        self.cursor_x += 1;
+    // The selected text from its first character to the one after its
+    // last, None when nothing is selected.
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        self.main_cursor().selection()
+    }
+
+    fn selected_rows(&self) -> Range<usize> {
+        self.main_cursor().rows()
+    }
+
+    // The lines of every cursor, each of them once.
+    fn cursor_rows(&self) -> Vec<usize> {
+        let mut rows: Vec<usize> = self
+            .cursors
+            .iter()
+            .chain([&self.main_cursor()])
+            .flat_map(Cursor::rows)
+            .collect();
+        rows.sort_unstable();
+        rows.dedup();
+        rows
+    }
+
+    fn main_cursor(&self) -> Cursor {
+        Cursor {
+            y: self.cursor_y,
+            x: self.cursor_x,
+            anchor: self.anchor,
+            goal_col: self.goal_col,
+        }
+    }
+
+    fn set_main_cursor(&mut self, cursor: Cursor) {
+        self.cursor_y = cursor.y;
+        self.cursor_x = cursor.x;
+        self.anchor = cursor.anchor;
+        self.goal_col = cursor.goal_col;
+    }
+
+    // Every cursor in file order, and which of them is the main one.
+    fn take_cursors(&mut self) -> (Vec<Cursor>, usize) {
+        let main = self.main_cursor();
+        let mut all = std::mem::take(&mut self.cursors);
+        let index = all.partition_point(|cursor| (cursor.y, cursor.x) < (main.y, main.x));
+        all.insert(index, main);
+        (all, index)
+    }
+
+    // Makes `all[main]` the main cursor again. Cursors that have run into
+    // each other become one.
+    fn put_cursors(&mut self, mut all: Vec<Cursor>, main: usize) {
+        let main = all.remove(main);
+        self.set_main_cursor(main);
+        all.retain(|cursor| (cursor.y, cursor.x) != (main.y, main.x));
+        all.sort_by_key(|cursor| (cursor.y, cursor.x));
+        all.dedup_by_key(|cursor| (cursor.y, cursor.x));
+        self.cursors = all;
+    }
+
+    // Moves every cursor with `f`.
+    fn each_cursor(&mut self, f: impl Fn(&mut Self)) {
+        let (mut all, main) = self.take_cursors();
+        for cursor in &mut all {
+            self.set_main_cursor(*cursor);
+            f(self);
+            *cursor = self.main_cursor();
+        }
+        self.put_cursors(all, main);
+    }
+
+    // Runs `edit` at every cursor as one undo step. It goes from the end of
+    // the file back, and after each edit the cursors behind it are moved
+    // with their text: the rest of the line the edit ended on is where the
+    // edit leaves the end of that line. Auto-inserted closers past the
+    // cursor are moved the same way.
+    fn at_cursors(&mut self, edit: impl Fn(&mut Self)) {
+        if self.cursors.is_empty() {
+            return edit(self);
+        }
+        let (mut all, main) = self.take_cursors();
+        self.history.start_group((all[main].y, all[main].x));
+        for i in (0..all.len()).rev() {
+            let cursor = all[i];
+            self.set_main_cursor(cursor);
+            let at = (cursor.y, cursor.x);
+            let last = cursor.anchor.map_or(at, |anchor| anchor.max(at));
+            let end = last.0;
+            let (end_len, total) = (self.lines[end].chars().count(), self.lines.len());
+            let (later_closers, closers) =
+                self.closers.drain(..).partition(|&closer| closer > last);
+            self.closers = closers;
+            edit(self);
+            all[i] = self.main_cursor();
+            let lines = &self.lines;
+            let shift = |(y, x): (usize, usize)| match y {
+                _ if y > end => (y + lines.len() - total, x),
+                _ if y == end => {
+                    let tail = end + lines.len() - total;
+                    (tail, lines[tail].chars().count().saturating_sub(end_len - x))
+                }
+                _ => (y, x),
+            };
+            for later in &mut all[i + 1..] {
+                (later.y, later.x) = shift((later.y, later.x));
+                later.anchor = later.anchor.map(shift);
+                later.goal_col = None;
+            }
+            self.closers.extend(later_closers.into_iter().map(shift));
+        }
+        self.history.end_group();
+        self.put_cursors(all, main);
+    }
+
+    // Ctrl+Alt+Up and Ctrl+Alt+Down: a cursor on the line above the first
+    // cursor or below the last one. The new cursor is the main one.
+    fn add_cursor(&mut self, down: bool) {
+        let main = self.main_cursor();
+        let edge = match down {
+            true => self.cursors.last().filter(|last| last.y > main.y),
+            false => self.cursors.first().filter(|first| first.y < main.y),
+        };
+        let y = edge.map_or(main.y, |cursor| cursor.y);
+        let y = match down {
+            true => y + 1,
+            false => match y.checked_sub(1) {
+                Some(y) => y,
+                None => return,
+            },
+        };
+        if y >= self.lines.len() {
+            return;
+        }
+        self.cursors.push(main);
+        self.anchor = None;
+        self.move_vertically(y);
+        let (all, main) = self.take_cursors();
+        self.put_cursors(all, main);
+    }
+
+    // Ctrl+D: selects the word at the cursor, then adds a cursor selecting
+    // the next place with the same text.
+    fn add_next_occurrence(&mut self) {
+        let Some(((y1, x1), (y2, x2))) = self.selection() else {
+            let (start, end) = text::word_at(&self.lines[self.cursor_y], self.cursor_x);
+            if start < end {
+                self.anchor = Some((self.cursor_y, start));
+                self.cursor_x = end;
+            }
+            return;
+        };
+        if y1 != y2 {
+            self.messages.warning("Only text within a line can be added");
+            return;
+        }
+        let line = &self.lines[y1];
+        let needle = &line[text::byte_index(line, x1)..text::byte_index(line, x2)];
+        let taken: Vec<(usize, usize)> = self
+            .cursors
+            .iter()
+            .filter_map(|cursor| cursor.anchor.map(|anchor| anchor.min((cursor.y, cursor.x))))
+            .chain([(y1, x1)])
+            .collect();
+        let mut found = Vec::new();
+        for (y, line) in self.lines.iter().enumerate() {
+            for (i, _) in line.match_indices(needle) {
+                found.push((y, line[..i].chars().count()));
+            }
+        }
+        let next = found
+            .iter()
+            .filter(|&&at| at > (y1, x1))
+            .chain(found.iter())
+            .find(|at| !taken.contains(at));
+        let Some(&(y, x)) = next else {
+            self.messages.info("No more occurrences");
+            return;
+        };
+        self.cursors.push(self.main_cursor());
+        self.anchor = Some((y, x));
+        self.cursor_y = y;
+        self.cursor_x = x + (x2 - x1);
+        self.goal_col = None;
+        let (all, main) = self.take_cursors();
+        self.put_cursors(all, main);
+    }
+
+    // Alt+Shift+arrows: starts or grows the block selection by a line or a
+    // character. Past the end of a line it goes a column at a time.
+    fn select_block(&mut self, key: Key) {
+        let tab_width = self.settings.tab_width;
+        let mut block = self
+            .block
+            .unwrap_or_else(|| Block::at(self.cursor_y, self.cursor_col()));
+        let (y, col) = block.cursor;
+        block.cursor = match key {
+            Key::Up => (y.saturating_sub(1), col),
+            Key::Down => ((y + 1).min(self.lines.len() - 1), col),
+            Key::Left => (y, block::step(&self.lines[y], col, false, tab_width)),
+            Key::Right => (y, block::step(&self.lines[y], col, true, tab_width)),
+            _ => return,
+        };
+        self.set_block(block);
+    }
+
+    // Alt+click starts a block selection and Alt+drag grows it.
+    fn mouse_block(&mut self, drag: bool, x: u16, y: u16) {
+        if y as usize > self.text_height() {
+            return;
+        }
+        let row = (y as usize - 1 + self.row_offset).min(self.lines.len() - 1);
+        let col = (x as usize - 1).saturating_sub(self.gutter_width()) + self.col_offset;
+        let block = match self.block {
+            Some(block) if drag => Block {
+                cursor: (row, col),
+                ..block
+            },
+            _ => Block::at(row, col),
+        };
+        self.set_block(block);
+    }
+
+    fn set_block(&mut self, block: Block) {
+        let (y, col) = block.cursor;
+        let line = &self.lines[y];
+        self.block = Some(block);
+        self.anchor = None;
+        self.cursors.clear();
+        self.cursor_y = y;
+        self.cursor_x = block::char_range(line, col..col, self.settings.tab_width).start;
+    }
+
+    fn block_rows(&self, block: Block) -> Range<usize> {
+        *block.rows().start()..block.rows().end() + 1
+    }
+
+    // Replaces the block with `c` on every line, lines too short to reach it
+    // are padded with spaces.
+    fn type_in_block(&mut self, block: Block, c: char) {
+        let tab_width = self.settings.tab_width;
+        let columns = block.columns();
+        self.history.start_group((self.cursor_y, self.cursor_x));
+        self.record(self.block_rows(block), EditKind::Insert);
+        for y in block.rows() {
+            let line = &mut self.lines[y];
+            block::pad(line, columns.start, tab_width);
+            let chars = block::char_range(line, columns.clone(), tab_width);
+            let bytes = text::byte_index(line, chars.start)..text::byte_index(line, chars.end);
+            line.replace_range(bytes, c.encode_utf8(&mut [0; 4]));
+        }
+        self.history.end_group();
+        let line = &self.lines[block.cursor.0];
+        let at = block::char_range(line, columns.start..columns.start, tab_width).start;
+        let col = text::display_col(line, at + 1, tab_width);
+        self.set_block(Block {
+            anchor: (block.anchor.0, col),
+            cursor: (block.cursor.0, col),
+        });
+    }
+
+    // Deletes display columns `columns` on the lines of the block, which
+    // then has no width.
+    fn delete_in_block(&mut self, block: Block, columns: Range<usize>) {
+        let tab_width = self.settings.tab_width;
+        self.record(self.block_rows(block), EditKind::Delete);
+        let mut col = columns.start;
+        for y in block.rows() {
+            let line = &mut self.lines[y];
+            let chars = block::char_range(line, columns.clone(), tab_width);
+            if y == block.cursor.0 && !chars.is_empty() {
+                col = text::display_col(line, chars.start, tab_width);
+            }
+            let bytes = text::byte_index(line, chars.start)..text::byte_index(line, chars.end);
+            line.replace_range(bytes, "");
+        }
+        self.set_block(Block {
+            anchor: (block.anchor.0, col),
+            cursor: (block.cursor.0, col),
+        });
+    }
+
+    // Backspace and Delete in a block selection: without a width they take
+    // the character before or after it on every line.
+    fn delete_block(&mut self, block: Block, forward: bool) {
+        let columns = block.columns();
+        let columns = match (columns.is_empty(), forward) {
+            (false, _) => columns,
+            (true, true) => columns.start..columns.start + 1,
+            (true, false) if columns.start > 0 => columns.start - 1..columns.start,
+            (true, false) => return,
+        };
+        self.delete_in_block(block, columns);
+    }
+
+    // Ctrl+C: returns false when nothing is selected.
+    fn copy(&mut self) -> bool {
+        let tab_width = self.settings.tab_width;
+        let clipboard = if let Some(block) = self.block {
+            let pieces = block.rows().map(|y| {
+                let line = &self.lines[y];
+                let chars = block::char_range(line, block.columns(), tab_width);
+                line.chars().skip(chars.start).take(chars.len()).collect()
+            });
+            Clipboard::Block(pieces.collect())
+        } else if let Some(((y1, x1), (y2, x2))) = self.selection() {
+            let pieces = (y1..=y2).map(|y| {
+                let line = &self.lines[y];
+                let from = if y == y1 { x1 } else { 0 };
+                let to = if y == y2 { x2 } else { line.chars().count() };
+                line.chars().skip(from).take(to - from).collect()
+            });
+            Clipboard::Text(pieces.collect())
+        } else {
+            self.messages.info("Nothing selected");
+            return false;
+        };
+        self.clipboard = Some(clipboard);
+        true
+    }
+
+    // Ctrl+X
+    fn cut(&mut self) {
+        if !self.copy() {
+            return;
+        }
+        self.history.start_group((self.cursor_y, self.cursor_x));
+        match self.block {
+            Some(block) if !block.columns().is_empty() => {
+                self.delete_in_block(block, block.columns())
+            }
+            Some(_) => {}
+            None => {
+                self.delete_selection();
+            }
+        }
+        self.history.end_group();
+    }
+
+    // Ctrl+V: a block goes in as a column from the cursor down, over the
+    // block selection if there is one.
+    fn paste(&mut self) {
+        let Some(clipboard) = self.clipboard.clone() else {
+            self.messages.info("Nothing to paste");
+            return;
+        };
+        // Whatever the text replaces goes back with it in one undo.
+        self.history.start_group((self.cursor_y, self.cursor_x));
+        self.paste_clipboard(clipboard);
+        self.history.end_group();
+    }
+
+    fn paste_clipboard(&mut self, clipboard: Clipboard) {
+        let tab_width = self.settings.tab_width;
+        if let Some(block) = self.block.take() {
+            if !block.columns().is_empty() {
+                self.delete_in_block(block, block.columns());
+                self.block = None;
+            }
+            let top = *block.rows().start();
+            let line = &self.lines[top];
+            let col = block.columns().start;
+            self.cursor_y = top;
+            self.cursor_x = block::char_range(line, col..col, tab_width).start;
+        }
+        match clipboard {
+            Clipboard::Block(pieces) => {
+                let (y, col) = (self.cursor_y, self.cursor_col());
+                let end = (y + pieces.len()).min(self.lines.len());
+                self.anchor = None;
+                self.record(y..end, EditKind::Other);
+                block::paste(&mut self.lines, y, col, &pieces, tab_width);
+                let line = &self.lines[y];
+                let at = block::char_range(line, col..col, tab_width).start;
+                self.cursor_x = at + pieces.first().map_or(0, |piece| piece.chars().count());
+            }
+            Clipboard::Text(pieces) => {
+                self.delete_selection();
+                let Some((last, rest)) = pieces.split_last() else {
+                    return;
+                };
+                self.record(self.cursor_y..self.cursor_y + 1, EditKind::Other);
+                let line = &mut self.lines[self.cursor_y];
+                let tail = line.split_off(text::byte_index(line, self.cursor_x));
+                let mut x = self.cursor_x;
+                for piece in rest {
+                    self.lines[self.cursor_y].push_str(piece);
+                    self.cursor_y += 1;
+                    self.lines.insert(self.cursor_y, String::new());
+                    x = 0;
+                }
+                let line = &mut self.lines[self.cursor_y];
+                line.push_str(last);
+                line.push_str(&tail);
+                self.cursor_x = x + last.chars().count();
+            }
+        }
+    }
+
+    // Every edit goes through here first, so it can be undone.
+    fn record(&mut self, rows: Range<usize>, kind: EditKind) {
+        let cursor = (self.cursor_y, self.cursor_x);
+        self.history.record(&self.lines, rows, cursor, kind);
         self.is_changed = true;
+        if kind == EditKind::Other {
+            self.closers.clear();
+        }
+    }
+
+    // Removes the selected text. Returns false when there was none.
+    fn delete_selection(&mut self) -> bool {
+        let Some(((y1, x1), (y2, x2))) = self.selection() else {
+            self.anchor = None;
+            return false;
+        };
+        self.anchor = None;
+        self.record(y1..y2 + 1, EditKind::Other);
+        let tail = self.lines[y2][text::byte_index(&self.lines[y2], x2)..].to_string();
+        let start = text::byte_index(&self.lines[y1], x1);
+        self.lines[y1].truncate(start);
+        self.lines[y1].push_str(&tail);
+        self.lines.drain(y1 + 1..y2 + 1);
+        self.cursor_y = y1;
+        self.cursor_x = x1;
+        true
+    }
+
+    // A character typed on the keyboard, with auto-pairing.
+    fn type_char(&mut self, c: char) {
+        if self.selection().is_none() {
+            let pairs = pairs::for_syntax(&self.settings.pairs, self.highlighter.syntax_name());
+            let line = &self.lines[self.cursor_y];
+            let (before, after) = line.split_at(text::byte_index(line, self.cursor_x));
+            let at = (self.cursor_y, self.cursor_x);
+            if after.starts_with(c) && pairs::is_closer(pairs, c) && self.closers.contains(&at) {
+                self.closers.retain(|&closer| closer != at);
+                self.cursor_x += 1;
+                return;
+            }
+            if let Some(close) = pairs::closer(pairs, c)
+                && pairs::should_pair(self.highlighter.syntax_name(), c, close, before, after)
+                && !self.in_comment()
+            {
+                self.insert_str(&format!("{}{}", c, close));
+                self.cursor_x -= 1;
+                self.closers.push((self.cursor_y, self.cursor_x));
+                return;
+            }
+        }
+        self.insert_char(c);
+    }
+
+    // Whether the highlighter has the text before the cursor in a comment.
+    fn in_comment(&self) -> bool {
+        let Some(x) = self.cursor_x.checked_sub(1) else {
+            return false;
+        };
+        let kinds = self.highlighter.results().kinds(self.cursor_y, &self.lines[self.cursor_y]);
+        kinds.get(x) == Some(&Kind::Comment)
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.insert_str(c.encode_utf8(&mut [0; 4]));
+        let line = &self.lines[self.cursor_y];
+        let before = &line[..text::byte_index(line, self.cursor_x - 1)];
+        if self.settings.auto_indent
+            && indent::is_closer(c)
+            && before.trim_start_matches([' ', '\t']).is_empty()
+        {
+            self.align_closer();
+        }
+    }
+
+    // Inserts `text`, which has no line breaks, at the cursor as one edit.
+    fn insert_str(&mut self, text: &str) {
+        self.delete_selection();
+        self.record(self.cursor_y..self.cursor_y + 1, EditKind::Insert);
+        let (y, x) = (self.cursor_y, self.cursor_x);
+        let count = text.chars().count();
+        for closer in &mut self.closers {
+            if closer.0 == y && closer.1 >= x {
+                closer.1 += count;
+            }
+        }
+        let line = &mut self.lines[y];
+        line.insert_str(text::byte_index(line, x), text);
+        self.cursor_x += count;
+    }
+
+    // Gives a closing bracket typed at the start of a line the indentation
+    // of the line with its opening bracket, or one level less when there's
+    // none.
+    fn align_closer(&mut self) {
+        let results = self.highlighter.results();
+        let pair = brackets::pair(&self.lines, &results, self.cursor_y, self.cursor_x - 1);
+        drop(results);
+        let old = indent::leading(&self.lines[self.cursor_y]).to_string();
+        let new = match pair {
+            Some([_, (y, _)]) => indent::leading(&self.lines[y]).to_string(),
+            None => indent::dedent(&old, &self.settings),
+        };
+        self.lines[self.cursor_y].replace_range(..old.len(), &new);
+        self.cursor_x = self.cursor_x - old.chars().count() + new.chars().count();
+    }
+
+    fn insert_tab(&mut self) {
+        self.delete_selection();
+        if self.settings.expand_tabs {
+            let size = self.settings.indent_size;
+            self.insert_str(&" ".repeat(size - self.cursor_col() % size));
+        } else {
+            self.insert_str("\t");
+        }
+    }
+
+    // Deletes characters `from..to` of the cursor line.
+    fn delete_range(&mut self, from: usize, to: usize) {
+        let y = self.cursor_y;
+        self.record(y..y + 1, EditKind::Delete);
+        let line = &mut self.lines[y];
+        let range = text::byte_index(line, from)..text::byte_index(line, to);
+        line.replace_range(range, "");
+        self.closers.retain(|&(cy, cx)| cy != y || cx < from || cx >= to);
+        for closer in &mut self.closers {
+            if closer.0 == y && closer.1 >= to {
+                closer.1 -= to - from;
+            }
+        }
+        self.cursor_x = from;
+    }
+
+    // Delete and Ctrl+Delete. At the end of a line the next one is joined.
+    fn delete_forward(&mut self, word: bool) {
+        if self.delete_selection() {
+            return;
+        }
+        let line = &self.lines[self.cursor_y];
+        if self.cursor_x < line.chars().count() {
+            let to = match word {
+                true => text::next_word(line, self.cursor_x),
+                false => self.cursor_x + 1,
+            };
+            self.delete_range(self.cursor_x, to);
+        } else if self.cursor_y + 1 < self.lines.len() {
+            self.record(self.cursor_y..self.cursor_y + 2, EditKind::Delete);
+            self.closers.clear();
+            let next = self.lines.remove(self.cursor_y + 1);
+            self.lines[self.cursor_y].push_str(&next);
+        }
+    }
+
+    fn delete_word_backward(&mut self) {
+        if self.cursor_x == 0 || self.selection().is_some() {
+            self.delete_char();
+            return;
+        }
+        let from = text::prev_word(&self.lines[self.cursor_y], self.cursor_x);
+        self.delete_range(from, self.cursor_x);
     }
 
     fn delete_char(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
         if self.cursor_x > 0 {
+            self.record(self.cursor_y..self.cursor_y + 1, EditKind::Delete);
+            // Between an empty pair both characters go.
+            let pairs = pairs::for_syntax(&self.settings.pairs, self.highlighter.syntax_name());
+            let line = &self.lines[self.cursor_y];
+            let mut chars = line.chars().skip(self.cursor_x - 1);
+            let (previous, next) = (chars.next(), chars.next());
+            let count = match (previous.and_then(|c| pairs::closer(pairs, c)), next) {
+                (Some(close), Some(next)) if close == next => 2,
+                _ => 1,
+            };
+            let (y, x) = (self.cursor_y, self.cursor_x - 1);
+            self.closers.retain(|&(cy, cx)| cy != y || cx < x || cx >= x + count);
+            for closer in &mut self.closers {
+                if closer.0 == y && closer.1 > x {
+                    closer.1 -= count;
+                }
+            }
+            for _ in 1..count {
+                let line = &mut self.lines[self.cursor_y];
+                line.remove(text::byte_index(line, self.cursor_x));
+            }
             let line = &mut self.lines[self.cursor_y];
             let byte_ind = line
                 .char_indices()
@@ -118,17 +922,19 @@ impl Editor {
                 .unwrap_or(0);
             line.remove(byte_ind);
             self.cursor_x -= 1;
-            self.is_changed = true;
         } else if self.cursor_y > 0 {
+            self.record(self.cursor_y - 1..self.cursor_y + 1, EditKind::Delete);
+            self.closers.clear();
             let current_line = self.lines.remove(self.cursor_y);
             self.cursor_y -= 1;
             self.cursor_x = self.lines[self.cursor_y].chars().count();
             self.lines[self.cursor_y].push_str(&current_line);
-            self.is_changed = true;
         }
     }
 
     fn insert_new_line(&mut self) {
+        self.delete_selection();
+        self.record(self.cursor_y..self.cursor_y + 1, EditKind::Other);
         let line = &self.lines[self.cursor_y];
         let byte_pos = line
             .char_indices()
@@ -136,35 +942,255 @@ impl Editor {
             .map(|(i, _)| i)
             .unwrap_or(line.len());
 
-        let right = line[byte_pos..].to_string();
+        let mut right = line[byte_pos..].to_string();
         self.lines[self.cursor_y].truncate(byte_pos);
-        self.lines.insert(self.cursor_y + 1, right);
+
+        let mut indentation = String::new();
+        if self.settings.auto_indent {
+            let left = &self.lines[self.cursor_y];
+            let base = indent::leading(left).to_string();
+            if base.len() < left.len() {
+                right = right.trim_start_matches([' ', '\t']).to_string();
+            }
+            indentation = base.clone();
+            if indent::opens(self.highlighter.syntax_name(), left) {
+                indentation += &indent::unit(&self.settings);
+                // Enter between `{` and `}` puts the closer on a line of
+                // its own.
+                if right.starts_with(indent::is_closer) {
+                    self.lines.insert(self.cursor_y + 1, base + &right);
+                    right.clear();
+                }
+            }
+            let trimmed = self.lines[self.cursor_y].trim_end().len();
+            self.lines[self.cursor_y].truncate(trimmed);
+        }
+        self.lines.insert(self.cursor_y + 1, indentation.clone() + &right);
 
         self.cursor_y += 1;
-        self.cursor_x = 0;
-        self.is_changed = true;
+        self.cursor_x = indentation.chars().count();
+    }
+
+    fn match_bracket(&mut self) {
+        let results = self.highlighter.results();
+        let pair = brackets::pair(&self.lines, &results, self.cursor_y, self.cursor_x);
+        drop(results);
+        if let Some([_, (y, x)]) = pair {
+            self.cursor_y = y;
+            self.cursor_x = x;
+        }
+    }
+
+    // Moves the cursor, extending the selection with `select` or dropping
+    // it otherwise.
+    fn move_or_select(&mut self, key: Key, select: bool) {
+        self.each_cursor(|editor| {
+            match select {
+                true => {
+                    editor.anchor.get_or_insert((editor.cursor_y, editor.cursor_x));
+                }
+                false => editor.anchor = None,
+            }
+            editor.move_cursor(key);
+        });
+    }
+
+    // Tab: the selected lines get one more level, without a selection it's
+    // inserted at the cursor. Lines with several cursors on them are
+    // indented once.
+    fn indent(&mut self) {
+        let selected = |cursor: &Cursor| cursor.selection().is_some();
+        if !selected(&self.main_cursor()) && !self.cursors.iter().any(selected) {
+            self.at_cursors(Self::insert_tab);
+            return;
+        }
+        let rows = self.cursor_rows();
+        self.record(rows[0]..rows[rows.len() - 1] + 1, EditKind::Other);
+        let unit = indent::unit(&self.settings);
+        for y in rows {
+            if !self.lines[y].is_empty() {
+                self.lines[y].insert_str(0, &unit);
+                self.shift_x(y, unit.chars().count(), 0);
+            }
+        }
+    }
+
+    // Shift+Tab: one level less for the selected lines or the cursor line.
+    fn dedent(&mut self) {
+        let rows = self.cursor_rows();
+        let changed: Vec<(usize, String)> = rows
+            .iter()
+            .filter_map(|&y| {
+                let old = indent::leading(&self.lines[y]);
+                let new = indent::dedent(old, &self.settings);
+                (new != old).then_some((y, new))
+            })
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+        self.record(rows[0]..rows[rows.len() - 1] + 1, EditKind::Other);
+        for (y, new) in changed {
+            self.replace_indentation(y, &new);
+        }
+    }
+
+    // Rewrites the indentation of every line with tabs or spaces as the
+    // settings say.
+    fn retab(&mut self) {
+        let changed: Vec<(usize, String)> = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter_map(|(y, line)| {
+                let old = indent::leading(line);
+                let new = indent::retab(old, &self.settings);
+                (new != old).then_some((y, new))
+            })
+            .collect();
+        let (Some(&(first, _)), Some(&(last, _))) = (changed.first(), changed.last()) else {
+            self.messages.info("Indentation already matches the settings");
+            return;
+        };
+        self.record(first..last + 1, EditKind::Other);
+        let count = changed.len();
+        for (y, new) in changed {
+            self.replace_indentation(y, &new);
+        }
+        self.messages.info(format!("Retabbed {} lines", count));
+    }
+
+    fn replace_indentation(&mut self, y: usize, new: &str) {
+        let old = indent::leading(&self.lines[y]);
+        let removed = old.chars().count();
+        let len = old.len();
+        self.lines[y].replace_range(..len, new);
+        self.shift_x(y, new.chars().count(), removed);
+    }
+
+    // Keeps the cursors and their anchors on line `y` next to the same text
+    // after `removed` characters at its start were replaced by `added`
+    // others. Positions at the start of the line stay there.
+    fn shift_x(&mut self, y: usize, added: usize, removed: usize) {
+        let shift = |x: usize| match x {
+            0 => 0,
+            x if x < removed => added,
+            x => x - removed + added,
+        };
+        let shift_cursor = |cursor: &mut Cursor| {
+            if cursor.y == y {
+                cursor.x = shift(cursor.x);
+            }
+            if let Some((anchor_y, anchor_x)) = &mut cursor.anchor
+                && *anchor_y == y
+            {
+                *anchor_x = shift(*anchor_x);
+            }
+        };
+        let mut main = self.main_cursor();
+        shift_cursor(&mut main);
+        self.set_main_cursor(main);
+        self.cursors.iter_mut().for_each(shift_cursor);
+    }
+
+    // Comments out the selected lines or the cursor line, or uncomments
+    // them when they all are comments already.
+    fn toggle_comment(&mut self) {
+        let Some(tokens) = self.highlighter.comment_tokens() else {
+            self.messages.warning(format!("No comment syntax for {}", self.highlighter.syntax_name()));
+            return;
+        };
+        let rows = self.selected_rows();
+        let Some(new) = comment::toggle(&self.lines[rows.clone()], &tokens) else {
+            return;
+        };
+        self.record(rows.clone(), EditKind::Other);
+        for (y, line) in rows.zip(new) {
+            let old = self.lines[y].chars().count();
+            let added = line.chars().count() as isize - old as isize;
+            self.lines[y] = line;
+            // Keep the cursor next to the same text.
+            let len = self.lines[y].chars().count();
+            if self.cursor_y == y {
+                self.cursor_x = self.cursor_x.saturating_add_signed(added).min(len);
+            }
+            if let Some((anchor_y, anchor_x)) = &mut self.anchor
+                && *anchor_y == y
+                && *anchor_x > 0
+            {
+                *anchor_x = anchor_x.saturating_add_signed(added).min(len);
+            }
+        }
+    }
+
+    fn undo(&mut self, redo: bool) {
+        let cursor = match redo {
+            false => self.history.undo(&mut self.lines),
+            true => self.history.redo(&mut self.lines),
+        };
+        match cursor {
+            Some((y, x)) => {
+                self.anchor = None;
+                self.closers.clear();
+                self.cursor_y = y;
+                self.cursor_x = x;
+                self.is_changed = true;
+            }
+            None if redo => self.messages.info("Nothing to redo"),
+            None => self.messages.info("Nothing to undo"),
+        }
     }
 
     fn move_cursor(&mut self, key: Key) {
         match key {
-            Key::Up => {
-                if self.cursor_y > 0 {
-                    self.cursor_y -= 1;
-                    let line_len = self.lines[self.cursor_y].chars().count();
-                    if self.cursor_x > line_len {
-                        self.cursor_x = line_len;
-                    }
+            Key::CtrlRight => {
+                let line = &self.lines[self.cursor_y];
+                if self.cursor_x < line.chars().count() {
+                    self.cursor_x = text::next_word(line, self.cursor_x);
+                } else if self.cursor_y + 1 < self.lines.len() {
+                    self.cursor_x = 0;
+                    self.cursor_y += 1;
                 }
             }
-            Key::Down => {
-                if self.cursor_y + 1 < self.lines.len() {
-                    self.cursor_y += 1;
-                    let line_len = self.lines[self.cursor_y].chars().count();
-                    if self.cursor_x > line_len {
-                        self.cursor_x = line_len;
-                    }
+            Key::CtrlLeft => {
+                if self.cursor_x > 0 {
+                    self.cursor_x = text::prev_word(&self.lines[self.cursor_y], self.cursor_x);
+                } else if self.cursor_y > 0 {
+                    self.cursor_y -= 1;
+                    self.cursor_x = self.lines[self.cursor_y].chars().count();
                 }
             }
+            Key::Up => self.move_vertically(self.cursor_y.saturating_sub(1)),
+            Key::Down => self.move_vertically((self.cursor_y + 1).min(self.lines.len() - 1)),
+            // A page away, with the cursor on the same screen row.
+            Key::PageUp => {
+                let page = self.text_height().max(1);
+                let y = self.cursor_y.saturating_sub(page);
+                self.row_offset = self.row_offset.saturating_sub(self.cursor_y - y);
+                self.move_vertically(y);
+            }
+            Key::PageDown => {
+                let page = self.text_height().max(1);
+                let y = (self.cursor_y + page).min(self.lines.len() - 1);
+                let max_offset = self.lines.len().saturating_sub(page);
+                self.row_offset = (self.row_offset + y - self.cursor_y).min(max_offset);
+                self.move_vertically(y);
+            }
+            // The first non-blank character, or the start of the line when
+            // the cursor is there already.
+            Key::Home => {
+                let first = indent::leading(&self.lines[self.cursor_y]).chars().count();
+                self.cursor_x = if self.cursor_x == first { 0 } else { first };
+            }
+            Key::End => self.cursor_x = self.lines[self.cursor_y].chars().count(),
+            Key::CtrlHome => {
+                self.cursor_y = 0;
+                self.cursor_x = 0;
+            }
+            Key::CtrlEnd => {
+                self.cursor_y = self.lines.len() - 1;
+                self.cursor_x = self.lines[self.cursor_y].chars().count();
+            }
             Key::Right => {
                 if self.cursor_x < self.lines[self.cursor_y].chars().count() {
                     self.cursor_x += 1;
@@ -181,79 +1207,555 @@ impl Editor {
                     self.cursor_x = self.lines[self.cursor_y].chars().count();
                 }
             }
-
-            _ => todo!(),
+            _ => {}
         }
     }
 
-    fn write_file(&mut self, filename: &String) -> io::Result<()> {
-        let file = File::create(format!("{}", filename))?;
-        let mut writer = BufWriter::new(file);
+    // Moves to line `y`, as close to the column the cursor had before it
+    // started crossing shorter lines as possible.
+    fn move_vertically(&mut self, y: usize) {
+        let col = match self.goal_col {
+            Some((col, at)) if at == (self.cursor_y, self.cursor_x) => col,
+            _ => self.cursor_col(),
+        };
+        self.cursor_y = y;
+        self.cursor_x = text::char_at_col(&self.lines[y], col, self.settings.tab_width);
+        self.goal_col = Some((col, (self.cursor_y, self.cursor_x)));
+    }
 
-        for line in &self.lines {
-            writeln!(writer, "{}", line)?;
+    fn write_file(&mut self) -> io::Result<()> {
+        if self.unread {
+            return Err(io::Error::other("the file could not be read, it would be lost"));
         }
-        writer.flush()?;
+        if self.file_style.trim_trailing_whitespace
+            && self.lines.iter().any(|line| line.ends_with([' ', '\t']))
+        {
+            self.record(0..self.lines.len(), EditKind::Other);
+            for line in &mut self.lines {
+                let len = line.trim_end_matches([' ', '\t']).len();
+                line.truncate(len);
+            }
+            let (mut all, main) = self.take_cursors();
+            let lines = &self.lines;
+            let clamp = |(y, x): (usize, usize)| (y, x.min(lines[y].chars().count()));
+            for cursor in &mut all {
+                (cursor.y, cursor.x) = clamp((cursor.y, cursor.x));
+                cursor.anchor = cursor.anchor.map(clamp);
+            }
+            self.put_cursors(all, main);
+        }
+
+        let eol = self.line_ending.as_str();
+        let mut content = self.lines.join(eol);
+        if self.file_style.final_newline != Some(false) {
+            content.push_str(eol);
+        }
+
+        std::fs::write(&self.filename, self.encoding.encode(&content))?;
         self.is_changed = false;
+        // Marks moved by edits are kept along with the text they are on.
+        self.save_marks();
         Ok(())
     }
 
-    fn load_file(filename: &String) -> Self {
-        if let Ok(content) = std::fs::read_to_string(filename) {
-            let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
-            Editor {
-                lines: if lines.is_empty() {
-                    vec![String::new()]
-                } else {
-                    lines
-                },
-                cursor_x: 0,
-                cursor_y: 0,
-                row_offset: 0,
-                col_offset: 0,
-                is_changed: false,
-                highlighter: highlight::Highlighter::new(filename),
+    fn save(&mut self) -> bool {
+        match self.write_file() {
+            Ok(()) => {
+                self.messages.info(format!(
+                    "\"{}\" written, {} lines",
+                    self.filename,
+                    self.lines.len()
+                ));
+                true
+            }
+            Err(err) => {
+                self.messages
+                    .error(format!("Can't write \"{}\": {}", self.filename, err));
+                false
+            }
+        }
+    }
+
+    fn load_file(filename: &str, config: &Config) -> Self {
+        let mut editor = Editor::new(filename, config);
+        // Files without a byte order mark can't be told apart from UTF-8,
+        // so `charset` decides how they are read.
+        let charset = editor.file_style.encoding;
+        let bytes = match std::fs::read(filename) {
+            Ok(bytes) => Some(bytes),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                editor.messages.error(format!("Can't read \"{}\": {}", filename, err));
+                editor.unread = true;
+                None
+            }
+        };
+        let decoded = bytes.as_ref().and_then(|bytes| match charset {
+            Some(encoding @ (Encoding::Latin1 | Encoding::Utf16Le | Encoding::Utf16Be)) => {
+                encoding.decode_as(bytes).map(|content| (encoding, content))
+            }
+            _ => Encoding::decode(bytes),
+        });
+        if bytes.is_some() && decoded.is_none() {
+            let encoding = charset.unwrap_or(Encoding::Utf8).name();
+            editor
+                .messages
+                .error(format!("Can't read \"{}\": not valid {}", filename, encoding));
+            editor.unread = true;
+        }
+        editor.read_only = editor.unread;
+
+        if let Some((encoding, content)) = decoded {
+            let line_ending = LineEnding::detect(&content);
+            let lines = line_ending.split(&content);
+            if !lines.is_empty() {
+                editor.lines = lines;
+            }
+            editor.encoding = encoding;
+            editor.line_ending = line_ending;
+            editor.read_only = !is_writable(filename);
+        }
+        // The file is converted when it is written.
+        editor.encoding = charset.unwrap_or(editor.encoding);
+        editor.line_ending = editor.file_style.line_ending.unwrap_or(editor.line_ending);
+        editor.highlighter.detect(filename, &editor.lines);
+        for error in editor.highlighter.take_errors() {
+            editor.messages.error(error);
+        }
+        match Marks::load(filename) {
+            Ok(marks) => editor.marks = marks,
+            Err(err) => editor.messages.error(format!("Can't read marks: {}", err)),
+        }
+        editor
+    }
+
+    fn search(&mut self, term: &mut Terminal) -> io::Result<()> {
+        let query = Prompt::new("search", "Search: ").run(term, |term| self.refresh(term))?;
+        let query = match query {
+            Some(query) if query.is_empty() => prompt::last_entry("search"),
+            query => query,
+        };
+        if let Some(query) = query {
+            let from = self.position();
+            self.find_next(&query);
+            if (self.cursor_y, self.cursor_x) != (from.y, from.x) {
+                self.jumps.push(from);
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> Jump {
+        Jump {
+            filename: self.filename.clone(),
+            y: self.cursor_y,
+            x: self.cursor_x,
+        }
+    }
+
+    // Scrolls so that the cursor line is in the middle of the screen.
+    fn center(&mut self) {
+        self.row_offset = self.cursor_y.saturating_sub(self.text_height() / 2);
+    }
+
+    // Ctrl+G: `42` or `42:7`, counting from 1.
+    fn go_to_line(&mut self, term: &mut Terminal) -> io::Result<()> {
+        let input = Prompt::new("line", "Go to line: ").run(term, |term| self.refresh(term))?;
+        let Some(input) = input.filter(|input| !input.trim().is_empty()) else {
+            return Ok(());
+        };
+        let (line, col) = input.trim().split_once(':').unwrap_or((input.trim(), "1"));
+        let (Ok(line), Ok(col)) = (line.trim().parse::<usize>(), col.trim().parse::<usize>()) else {
+            self.messages.error(format!("Not a line number: {}", input));
+            return Ok(());
+        };
+        self.jumps.push(self.position());
+        self.anchor = None;
+        self.cursor_y = line.clamp(1, self.lines.len()) - 1;
+        self.cursor_x = col.saturating_sub(1).min(self.lines[self.cursor_y].chars().count());
+        self.center();
+        Ok(())
+    }
+
+    // Ctrl+O: opens another file in place of this one.
+    fn open(&mut self, term: &mut Terminal) -> io::Result<()> {
+        let dir = Path::new(&self.filename).parent().unwrap_or(Path::new(""));
+        let initial = match dir.as_os_str().is_empty() {
+            true => String::new(),
+            false => format!("{}/", dir.display()),
+        };
+        let input = Prompt::new("path", "Open: ")
+            .initial(&initial)
+            .completer(Box::new(prompt::complete_path))
+            .run(term, |term| self.refresh(term))?;
+        let Some(input) = input.filter(|input| !input.is_empty()) else {
+            return Ok(());
+        };
+        let filename = prompt::expand_home(&input);
+        if Path::new(&filename).is_dir() {
+            self.messages.error(format!("{} is a directory", filename));
+            return Ok(());
+        }
+        let from = self.position();
+        if self.switch_file(term, &filename)? {
+            self.jumps.push(from);
+        }
+        Ok(())
+    }
+
+    // Replaces the buffer with `filename`, after offering to save changes.
+    // Returns false when the file stays open.
+    fn switch_file(&mut self, term: &mut Terminal, filename: &str) -> io::Result<bool> {
+        if filename == self.filename {
+            return Ok(true);
+        }
+        if self.is_changed {
+            let save = prompt::confirm(term, "Save changes?", |term| self.refresh(term))?;
+            if save && !self.save() {
+                return Ok(false);
             }
+        }
+        let mut config = self.config.reload(Path::new(filename));
+        let mut editor = Editor::load_file(filename, &config);
+        for error in config.errors.drain(..) {
+            editor.messages.error(error);
+        }
+        editor.jumps = std::mem::take(&mut self.jumps);
+        editor.clipboard = self.clipboard.take();
+        editor.resize(term.size());
+        editor.highlighter.start(term.sender());
+        *self = editor;
+        Ok(true)
+    }
+
+    // Alt+Left and Alt+Right, reopening the file of the position if needed.
+    fn jump(&mut self, term: &mut Terminal, forward: bool) -> io::Result<()> {
+        let jump = match forward {
+            true => self.jumps.forward(),
+            false => self.jumps.back(self.position()),
+        };
+        let Some(jump) = jump else {
+            return Ok(());
+        };
+        if !self.switch_file(term, &jump.filename)? {
+            return Ok(());
+        }
+        self.anchor = None;
+        self.cursor_y = jump.y.min(self.lines.len() - 1);
+        self.cursor_x = jump.x.min(self.lines[self.cursor_y].chars().count());
+        self.center();
+        Ok(())
+    }
+
+    fn save_marks(&mut self) {
+        if let Err(err) = self.marks.save() {
+            self.messages.error(format!("Can't write marks: {}", err));
+        }
+    }
+
+    // Alt+K: a letter sets that mark on the cursor, or removes it when it is
+    // already on this line.
+    fn set_mark(&mut self, term: &mut Terminal) -> io::Result<()> {
+        let input = Prompt::new("mark", "Set mark (a-z, A-Z for any file): ")
+            .run(term, |term| self.refresh(term))?;
+        let Some(input) = input.filter(|input| !input.is_empty()) else {
+            return Ok(());
+        };
+        let mut chars = input.chars();
+        let (Some(name), None) = (chars.next(), chars.next()) else {
+            self.messages.error(format!("Not a mark: {}", input));
+            return Ok(());
+        };
+        if !marks::is_valid(name) {
+            self.messages.error(format!("Not a mark: {}", input));
+            return Ok(());
+        }
+        if self.marks.get(name).is_some_and(|(y, _)| y == self.cursor_y) {
+            self.marks.remove(name);
+            self.messages.info(format!("Mark {} removed", name));
         } else {
-            Editor::new(filename)
+            self.marks.set(name, (self.cursor_y, self.cursor_x));
+        }
+        self.save_marks();
+        Ok(())
+    }
+
+    // Alt+': picks a mark of this file or an upper-case one of any file.
+    fn jump_to_mark(&mut self, term: &mut Terminal) -> io::Result<()> {
+        let elsewhere = match self.marks.elsewhere() {
+            Ok(elsewhere) => elsewhere,
+            Err(err) => {
+                self.messages.error(format!("Can't read marks: {}", err));
+                Vec::new()
+            }
+        };
+        let mut targets: Vec<Jump> = Vec::new();
+        let mut items = Vec::new();
+        for (name, (y, x)) in self.marks.iter() {
+            let text = self.lines.get(y).map_or("", |line| line.trim());
+            items.push(format!("{}  {}: {}", name, y + 1, text));
+            targets.push(Jump { filename: self.filename.clone(), y, x });
         }
+        for mark in elsewhere {
+            items.push(format!("{}  {}:{}", mark.name, mark.file, mark.y + 1));
+            targets.push(Jump { filename: mark.file, y: mark.y, x: mark.x });
+        }
+        if items.is_empty() {
+            self.messages.info("No marks");
+            return Ok(());
+        }
+        let chosen = picker::pick(term, "Mark: ", &items, 0, |term, _| self.refresh(term))?;
+        let Some(target) = chosen.map(|i| targets.swap_remove(i)) else {
+            return Ok(());
+        };
+        let from = self.position();
+        if !self.switch_file(term, &target.filename)? {
+            return Ok(());
+        }
+        self.jumps.push(from);
+        self.anchor = None;
+        self.cursor_y = target.y.min(self.lines.len() - 1);
+        self.cursor_x = target.x.min(self.lines[self.cursor_y].chars().count());
+        self.center();
+        Ok(())
     }
 
-    fn confirm<W: Write>(&self, stdout: &mut W, message: &str) -> io::Result<bool> {
-        let (_, height) = termion::terminal_size()?;
+    fn find_next(&mut self, query: &str) {
+        let total = self.lines.len();
 
-        write!(
-            stdout,
-            "{}{}{} (Y/n): {}",
-            cursor::Goto(1, height),
-            style::Invert,
-            message,
-            style::Reset,
-        )?;
-        stdout.flush()?;
+        for step in 0..=total {
+            let y = (self.cursor_y + step) % total;
+            let line = &self.lines[y];
+            let from = if step == 0 {
+                line.char_indices()
+                    .nth(self.cursor_x + 1)
+                    .map(|(i, _)| i)
+                    .unwrap_or(line.len())
+            } else {
+                0
+            };
 
-        let stdin = io::stdin();
-        for evt in stdin.events() {
-            match evt? {
-                Event::Key(Key::Char('y')) | Event::Key(Key::Char('Y')) => return Ok(true),
-                Event::Key(Key::Char('n')) | Event::Key(Key::Char('N')) => return Ok(false),
-                _ => {}
+            if let Some(i) = line[from..].find(query) {
+                if self.cursor_y + step >= total {
+                    self.messages.info("Search wrapped");
+                }
+                self.cursor_x = line[..from + i].chars().count();
+                self.cursor_y = y;
+                self.anchor = None;
+                return;
             }
         }
-        Ok(false)
+        self.messages.warning(format!("Not found: {}", query));
+    }
+
+    fn save_as(&mut self, term: &mut Terminal) -> io::Result<()> {
+        let name = Prompt::new("path", "Save as: ")
+            .initial(&self.filename)
+            .completer(Box::new(prompt::complete_path))
+            .run(term, |term| self.refresh(term))?;
+
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            let previous = std::mem::replace(&mut self.filename, prompt::expand_home(&name));
+            // Another file is safe to write even when this one couldn't be read.
+            let unread = self.unread;
+            self.unread = unread && self.filename == previous;
+            self.marks.rename(&self.filename);
+            if self.save() {
+                self.load_config();
+                self.highlighter.detect(&self.filename, &self.lines);
+                self.read_only = false;
+            } else {
+                self.filename = previous;
+                self.unread = unread;
+                self.marks.rename(&self.filename);
+            }
+        }
+        Ok(())
+    }
+
+    // Every theme is previewed while it is highlighted in the list.
+    fn select_theme(&mut self, term: &mut Terminal) -> io::Result<()> {
+        let names = theme::theme_names();
+        // The last match, as user themes come after the bundled ones they
+        // shadow.
+        let theme = &self.settings.theme;
+        let current = names.iter().rposition(|name| name.eq_ignore_ascii_case(theme));
+        let original = (self.highlighter.theme().clone(), self.palette);
+        let mut shown = current;
+
+        let chosen = picker::pick(term, "Theme: ", &names, current.unwrap_or(0), |term, i| {
+            if i != shown && let Some(i) = i {
+                match theme::load(&names[i]) {
+                    Ok(theme) => {
+                        self.palette = Palette::new(&theme, self.settings.background);
+                        self.highlighter.set_theme(theme);
+                    }
+                    Err(err) => self.messages.error(err),
+                }
+                shown = Some(i);
+            }
+            self.refresh(term)
+        })?;
+
+        match chosen {
+            Some(i) if shown == Some(i) => {
+                self.settings.theme = names[i].clone();
+                self.config.settings.theme = names[i].clone();
+            }
+            _ => {
+                self.highlighter.set_theme(original.0);
+                self.palette = original.1;
+            }
+        }
+        Ok(())
+    }
+
+    // Prompts and pickers take the resize events while they are open, so
+    // the size is checked on every redraw.
+    fn refresh(&mut self, term: &mut Terminal) -> io::Result<()> {
+        self.resize(term.size());
+        for error in self.highlighter.take_errors() {
+            self.messages.error(error);
+        }
+        self.scroll();
+        self.highlighter.update(&self.lines);
+        self.draw(term)
     }
 
-    fn draw<W: Write>(&self, stdout: &mut W, highlighted: &[String]) -> io::Result<()> {
+    fn draw_style<W: Write>(&self, stdout: &mut W, style: &Style, mark: Mark) -> io::Result<()> {
+        let palette = &self.palette;
+        let fg = color::Rgb(style.foreground.r, style.foreground.g, style.foreground.b);
+        let (bg, fg) = match mark {
+            Mark::Bracket => (palette.brackets_background, palette.brackets_foreground.unwrap_or(fg)),
+            Mark::Selected => (palette.selection, fg),
+            Mark::None => (palette.background, fg),
+        };
+        write!(stdout, "{}{}{}", style::Reset, colors::Bg(bg), colors::Fg(fg))?;
+        match mark {
+            Mark::Bracket => write!(stdout, "{}{}", style::Bold, colors::reverse())?,
+            Mark::Selected => write!(stdout, "{}", colors::reverse())?,
+            Mark::None => {}
+        }
+        if style.font_style.contains(FontStyle::BOLD) {
+            write!(stdout, "{}", style::Bold)?;
+        }
+        if style.font_style.contains(FontStyle::ITALIC) {
+            write!(stdout, "{}", style::Italic)?;
+        }
+        if style.font_style.contains(FontStyle::UNDERLINE) {
+            write!(stdout, "{}", style::Underline)?;
+        }
+        Ok(())
+    }
+
+    // Writes the visible part of a line, tabs are expanded and characters
+    // cut by the edges of the window are replaced by spaces. `mark` tells
+    // how to draw the character with the given index.
+    fn draw_line<W: Write>(
+        &self,
+        stdout: &mut W,
+        spans: &[Span],
+        mark: &dyn Fn(usize) -> Mark,
+    ) -> io::Result<()> {
+        let tab_width = self.settings.tab_width;
+        let background = self.palette.background;
+        let start = self.col_offset;
+        let end = start + self.text_width();
+        let mut col = 0;
+        let mut index = 0;
+
+        for (style, _, token) in spans {
+            let mut marked = mark(index);
+            self.draw_style(stdout, style, marked)?;
+
+            let mut visible = String::new();
+            for c in token.chars() {
+                if mark(index) != marked {
+                    marked = mark(index);
+                    write!(stdout, "{}", visible)?;
+                    visible.clear();
+                    self.draw_style(stdout, style, marked)?;
+                }
+                index += 1;
+                let width = text::char_width(c, col, tab_width);
+                if col >= start && col + width <= end && c != '\t' {
+                    visible.push(c);
+                } else if col + width > start && col < end {
+                    let shown = (col + width).min(end) - col.max(start);
+                    visible.extend(std::iter::repeat_n(' ', shown));
+                }
+                col += width;
+            }
+            write!(stdout, "{}", visible)?;
+            if col >= end {
+                break;
+            }
+        }
+        write!(stdout, "{}{}", style::Reset, colors::Bg(background))
+    }
+
+    // Draws the cell at line `y`, display column `col` in reverse video, as
+    // a cursor the terminal doesn't show. Nothing when it's off screen.
+    fn draw_reversed<W: Write>(&self, stdout: &mut W, y: usize, col: usize) -> io::Result<()> {
+        let tab_width = self.settings.tab_width;
+        let rows = self.row_offset..self.row_offset + self.text_height();
+        let columns = self.col_offset..self.col_offset + self.text_width();
+        if !rows.contains(&y) || !columns.contains(&col) {
+            return Ok(());
+        }
+        let line = &self.lines[y];
+        let i = block::char_range(line, col..col + 1, tab_width).start;
+        let c = line
+            .chars()
+            .nth(i)
+            .filter(|&c| c != '\t' && text::display_col(line, i, tab_width) == col);
+        write!(
+            stdout,
+            "{}{}{}{}{}{}",
+            cursor::Goto(
+                (self.gutter_width() + col - self.col_offset + 1) as u16,
+                (y - self.row_offset + 1) as u16
+            ),
+            colors::Bg(self.palette.background),
+            colors::Fg(self.palette.foreground),
+            style::Invert,
+            c.unwrap_or(' '),
+            style::Reset
+        )
+    }
+
+    fn draw<W: Write>(&self, stdout: &mut W) -> io::Result<()> {
+        let palette = &self.palette;
         write!(
             stdout,
             "{}{}{}",
             cursor::Hide,
             style::Reset,
-            color::Bg(color::Rgb(40, 42, 54))
+            colors::Bg(palette.background)
         )?;
+        let gutter = self.gutter_width();
 
-        let (_,height) = termion::terminal_size()?;
-        let visible_height = (height - 1) as usize;
+        let height = self.screen_height;
+        let visible_height = self.text_height();
+        let highlighted = self.highlighter.results();
+        let brackets = brackets::pair(&self.lines, &highlighted, self.cursor_y, self.cursor_x);
+        let tab_width = self.settings.tab_width;
+        let selection = self.selection();
+        let selections: Vec<_> = self
+            .cursors
+            .iter()
+            .filter_map(|cursor| {
+                let (anchor, at) = (cursor.anchor?, (cursor.y, cursor.x));
+                Some((anchor.min(at), anchor.max(at)))
+            })
+            .chain(selection)
+            .collect();
+        // Lines the highlighter hasn't got to yet are drawn in plain text.
+        let color::Rgb(r, g, b) = palette.foreground;
+        let plain = Style {
+            foreground: highlighting::Color { r, g, b, a: 0xff },
+            ..Style::default()
+        };
 
         for i in 0..visible_height {
             let file_row = i + self.row_offset;
@@ -262,31 +1764,105 @@ impl Editor {
                 stdout,
                 "{}{}{}",
                 cursor::Goto(1, i as u16 + 1),
-                color::Bg(color::Rgb(40, 42, 54)),
+                colors::Bg(palette.background),
                 clear::CurrentLine
             )?;
 
-            if file_row < highlighted.len() {
-                write!(stdout, "{}{}", highlighted[file_row], style::Reset)?;
+            if file_row < self.lines.len() {
+                if !self.marks.is_empty() {
+                    let sign = self.marks.on_line(file_row).unwrap_or(' ');
+                    write!(
+                        stdout,
+                        "{}{}{} ",
+                        colors::Bg(palette.gutter),
+                        colors::Fg(palette.gutter_foreground),
+                        sign
+                    )?;
+                }
+                if self.settings.line_numbers {
+                    write!(
+                        stdout,
+                        "{}{}{:>width$} ",
+                        colors::Bg(palette.gutter),
+                        colors::Fg(palette.gutter_foreground),
+                        file_row + 1,
+                        width = self.lines.len().to_string().len()
+                    )?;
+                }
+                let line = &self.lines[file_row];
+                let in_block = self
+                    .block
+                    .filter(|block| block.rows().contains(&file_row))
+                    .map(|block| block::char_range(line, block.columns(), tab_width));
+                let mark = |x: usize| {
+                    let at = (file_row, x);
+                    if selections.iter().any(|&(start, end)| start <= at && at < end)
+                        || in_block.as_ref().is_some_and(|chars| chars.contains(&x))
+                    {
+                        Mark::Selected
+                    } else if brackets.is_some_and(|pair| pair.contains(&at)) {
+                        Mark::Bracket
+                    } else {
+                        Mark::None
+                    }
+                };
+                match highlighted.get(file_row, line) {
+                    Some(spans) => self.draw_line(stdout, spans, &mark)?,
+                    None => self.draw_line(stdout, &[(plain, Kind::Code, line.clone())], &mark)?,
+                }
+                write!(stdout, "{}", style::Reset)?;
+            }
+        }
+
+        // The terminal shows the main cursor, the others are drawn reversed.
+        for other in &self.cursors {
+            let col = text::display_col(&self.lines[other.y], other.x, tab_width);
+            self.draw_reversed(stdout, other.y, col)?;
+        }
+        // So is a block selection without width on each of its lines.
+        if let Some(block) = self.block.filter(|block| block.columns().is_empty()) {
+            for y in block.rows().filter(|&y| y != block.cursor.0) {
+                self.draw_reversed(stdout, y, block.cursor.1)?;
             }
         }
 
+        let info = StatusInfo {
+            filename: &self.filename,
+            modified: self.is_changed,
+            read_only: self.read_only,
+            syntax: self.highlighter.syntax_name(),
+            encoding: self.encoding.name(),
+            line_ending: self.line_ending.name(),
+            line: self.cursor_y + 1,
+            col: self.cursor_x + 1,
+            total_lines: self.lines.len(),
+        };
         write!(
             stdout,
-            "{}{}{}Press Ctr+Q to quit | Line {}/{} Col {}",
-            cursor::Goto(1, height),
-            termion::style::Invert,
-            color::Fg(Yellow),
-            self.cursor_y + 1,
-            self.lines.len(),
-            self.cursor_x + 1,
+            "{}{}{}{}{}{}",
+            cursor::Goto(1, height - 1),
+            colors::Bg(palette.status_background),
+            colors::Fg(palette.status_foreground),
+            colors::reverse(),
+            self.statusline.render(&info, self.screen_width as usize),
+            style::Reset,
         )?;
+        self.messages.draw(stdout, height)?;
+        if !self.keymap.pending().is_empty() {
+            write!(
+                stdout,
+                "{}{}{}-",
+                cursor::Goto(1, height),
+                clear::CurrentLine,
+                keymap::format_sequence(self.keymap.pending())
+            )?;
+        }
 
         write!(
             stdout,
             "{}{}",
             cursor::Goto(
-                (self.cursor_x - self.col_offset + 1) as u16,
+                (gutter + self.cursor_col() - self.col_offset + 1) as u16,
                 (self.cursor_y - self.row_offset + 1) as u16
             ),
             cursor::Show
@@ -294,93 +1870,495 @@ impl Editor {
         stdout.flush()
     }
 
-    fn run(&mut self, filename: &String) -> io::Result<()> {
-        let stdin = io::stdin();
-        let mut stdout = MouseTerminal::from(stdout().into_raw_mode().unwrap());
-        let highlighted = self.highlighter.highlight_all(&self.lines);
-        self.scroll();
-        self.draw(&mut stdout, &highlighted)?;
-
-        for evt in stdin.events() {
-            match evt? {
-                Event::Key(Key::Ctrl('q')) => {
-                    if self.is_changed {
-                        let save = self.confirm(&mut stdout, "Save changes?(Y/n):")?;
-                        if save {
-                            self.write_file(filename)?;
-                        }
-                        write!(
-                            stdout,
-                            "{}{}{}\n",
-                            style::Reset,
-                            clear::All,
-                            cursor::Goto(1, 1)
-                        )?;
-                        break;
-                    } else {
-                        write!(
-                            stdout,
-                            "{}{}{}\n",
-                            style::Reset,
-                            clear::All,
-                            cursor::Goto(1, 1)
-                        )?;
+    // Returns true when the editor should close.
+    fn handle_key(&mut self, term: &mut Terminal, chord: Chord) -> io::Result<bool> {
+        match self.keymap.lookup(chord) {
+            Lookup::Command(command) => return self.execute(term, command),
+            Lookup::Pending => {}
+            Lookup::Unbound(keys) => match keys[..] {
+                [Chord { code: KeyCode::Char(c), ctrl: false, alt: false, .. }] => match self.block {
+                    Some(block) => self.type_in_block(block, c),
+                    None => self.at_cursors(|editor| editor.type_char(c)),
+                },
+                [_] => {}
+                _ => self
+                    .messages
+                    .warning(format!("{} is not bound", keymap::format_sequence(&keys))),
+            },
+        }
+        Ok(false)
+    }
 
-                        break;
+    fn execute(&mut self, term: &mut Terminal, command: Command) -> io::Result<bool> {
+        if !keeps_cursors(command) {
+            self.cursors.clear();
+        }
+        if !keeps_block(command) {
+            self.block = None;
+        }
+        match command {
+            Command::Quit => {
+                if self.is_changed {
+                    let save =
+                        prompt::confirm(term, "Save changes?", |term| self.refresh(term))?;
+                    if save && !self.save() {
+                        return Ok(false);
                     }
                 }
-                Event::Key(Key::Char('\n')) => self.insert_new_line(),
-                Event::Key(Key::Char(c)) => self.insert_char(c),
-                Event::Key(Key::Backspace) => self.delete_char(),
-                Event::Key(Key::Ctrl('s')) => self.write_file(filename)?,
-                Event::Key(Key::Ctrl('w')) => {
-                    self.write_file(filename)?;
-                    break;
+                return Ok(true);
+            }
+            Command::Save => {
+                self.save();
+            }
+            Command::SaveAndQuit => return Ok(self.save()),
+            Command::SaveAs => self.save_as(term)?,
+            Command::GoToLine => self.go_to_line(term)?,
+            Command::OpenFile => self.open(term)?,
+            Command::JumpBack => self.jump(term, false)?,
+            Command::JumpForward => self.jump(term, true)?,
+            Command::SetMark => self.set_mark(term)?,
+            Command::JumpToMark => self.jump_to_mark(term)?,
+            Command::Search => self.search(term)?,
+            Command::ShowMessages => self.messages.show_history(term)?,
+            Command::DismissMessage if !self.cursors.is_empty() => self.cursors.clear(),
+            Command::DismissMessage if self.block.is_some() => self.block = None,
+            Command::DismissMessage => self.messages.dismiss(),
+            Command::Execute => {
+                let name = Prompt::new("command", "Command: ")
+                    .completer(commands::completer(commands::EDITOR))
+                    .run(term, |term| self.refresh(term))?;
+                match name.as_deref().map(Command::from_name) {
+                    Some(Some(command)) if commands::EDITOR.contains(&command) => {
+                        return self.execute(term, command);
+                    }
+                    Some(_) => self
+                        .messages
+                        .error(format!("Unknown command: {}", name.unwrap_or_default())),
+                    None => {}
                 }
+            }
+            Command::MoveUp => self.move_or_select(Key::Up, false),
+            Command::MoveDown => self.move_or_select(Key::Down, false),
+            Command::MoveLeft => self.move_or_select(Key::Left, false),
+            Command::MoveRight => self.move_or_select(Key::Right, false),
+            Command::SelectUp => self.move_or_select(Key::Up, true),
+            Command::SelectDown => self.move_or_select(Key::Down, true),
+            Command::SelectLeft => self.move_or_select(Key::Left, true),
+            Command::SelectRight => self.move_or_select(Key::Right, true),
+            Command::Indent => self.indent(),
+            Command::Dedent => self.dedent(),
+            Command::Retab => self.retab(),
+            Command::Undo => self.undo(false),
+            Command::Redo => self.undo(true),
+            Command::ToggleComment => self.toggle_comment(),
+            Command::MoveHome => self.move_or_select(Key::Home, false),
+            Command::MoveEnd => self.move_or_select(Key::End, false),
+            Command::PageUp => self.move_or_select(Key::PageUp, false),
+            Command::PageDown => self.move_or_select(Key::PageDown, false),
+            Command::MoveFileStart => self.move_or_select(Key::CtrlHome, false),
+            Command::MoveFileEnd => self.move_or_select(Key::CtrlEnd, false),
+            Command::SelectHome => self.move_or_select(Key::Home, true),
+            Command::SelectEnd => self.move_or_select(Key::End, true),
+            Command::MoveWordLeft => self.move_or_select(Key::CtrlLeft, false),
+            Command::MoveWordRight => self.move_or_select(Key::CtrlRight, false),
+            Command::DeleteForward => match self.block {
+                Some(block) => self.delete_block(block, true),
+                None => self.delete_forward(false),
+            },
+            Command::DeleteWordBackward => self.delete_word_backward(),
+            Command::DeleteWordForward => self.delete_forward(true),
+            Command::InsertNewline => self.at_cursors(Self::insert_new_line),
+            Command::DeleteBackward => match self.block {
+                Some(block) => self.delete_block(block, false),
+                None => self.at_cursors(Self::delete_char),
+            },
+            Command::AddCursorAbove => self.add_cursor(false),
+            Command::AddCursorBelow => self.add_cursor(true),
+            Command::AddNextOccurrence => self.add_next_occurrence(),
+            Command::SelectBlockUp => self.select_block(Key::Up),
+            Command::SelectBlockDown => self.select_block(Key::Down),
+            Command::SelectBlockLeft => self.select_block(Key::Left),
+            Command::SelectBlockRight => self.select_block(Key::Right),
+            Command::Copy => {
+                self.copy();
+            }
+            Command::Cut => self.cut(),
+            Command::Paste => self.paste(),
+            Command::ReloadConfig => self.reload_config(),
+            Command::SelectTheme => self.select_theme(term)?,
+            Command::SelectSyntax => {
+                let names = self.highlighter.syntax_names();
+                let current = names
+                    .iter()
+                    .position(|name| name == self.highlighter.syntax_name());
+                let chosen = picker::pick(term, "Syntax: ", &names, current.unwrap_or(0), |term, _| {
+                    self.refresh(term)
+                })?;
+                if let Some(i) = chosen {
+                    self.highlighter.set_syntax(&names[i]);
+                }
+            }
+            Command::MatchBracket => self.match_bracket(),
+            _ => {}
+        }
+        Ok(false)
+    }
 
-                Event::Key(key @ Key::Up)
-                | Event::Key(key @ Key::Down)
-                | Event::Key(key @ Key::Left)
-                | Event::Key(key @ Key::Right) => self.move_cursor(key),
+    fn run(&mut self, term: &mut Terminal) -> io::Result<()> {
+        self.resize(term.size());
+        self.highlighter.start(term.sender());
+        self.refresh(term)?;
 
+        loop {
+            let evt = match term.poll_event(self.messages.time_left())? {
+                Some(TermEvent::Input(evt)) => evt,
+                None | Some(TermEvent::Highlighted) => {
+                    self.refresh(term)?;
+                    continue;
+                }
+                Some(TermEvent::Resize) => {
+                    write!(term, "{}", clear::All)?;
+                    self.refresh(term)?;
+                    continue;
+                }
+            };
+
+            match evt {
                 Event::Mouse(me) => match me{
-                    MouseEvent::Press(MouseButton::WheelUp,_,_) => self.scroll_for_mouse(-1,3),
-                    MouseEvent::Press(MouseButton::WheelDown,_,_) => self.scroll_for_mouse(1,3),
+                    MouseEvent::Press(MouseButton::WheelUp,_,_) => {
+                        self.scroll_for_mouse(-1, self.settings.mouse_scroll)
+                    }
+                    MouseEvent::Press(MouseButton::WheelDown,_,_) => {
+                        self.scroll_for_mouse(1, self.settings.mouse_scroll)
+                    }
                     MouseEvent::Press(MouseButton::Left,x,y) => self.mouse_click(x,y),
                     _ => {}
                 }
-                _ => {}
+                evt => {
+                    if let Event::Unsupported(bytes) = &evt
+                        && let Some((drag, x, y)) = terminal::alt_mouse(bytes)
+                    {
+                        self.mouse_block(drag, x, y);
+                    } else if let Some(chord) = Chord::from_event(&evt)
+                        && self.handle_key(term, chord)?
+                    {
+                        break;
+                    }
+                }
+            }
+            self.history.commit(&self.lines, (self.cursor_y, self.cursor_x));
+            for edit in self.history.take_edits() {
+                self.marks.shift(edit);
             }
 
-            self.scroll();
-            let highlighted = self.highlighter.highlight_all(&self.lines);
-            self.draw(&mut stdout, &highlighted)?;
+            self.refresh(term)?;
         }
-        write!(stdout, "{}", clear::All)?;
         Ok(())
     }
 }
 
+// Commands that work with several cursors, or don't move any. The rest
+// leave only the main cursor.
+fn keeps_cursors(command: Command) -> bool {
+    matches!(
+        command,
+        Command::Save
+            | Command::SaveAs
+            | Command::ShowMessages
+            | Command::DismissMessage
+            | Command::Execute
+            | Command::ReloadConfig
+            | Command::SelectTheme
+            | Command::SelectSyntax
+            | Command::MoveUp
+            | Command::MoveDown
+            | Command::MoveLeft
+            | Command::MoveRight
+            | Command::SelectUp
+            | Command::SelectDown
+            | Command::SelectLeft
+            | Command::SelectRight
+            | Command::MoveHome
+            | Command::MoveEnd
+            | Command::SelectHome
+            | Command::SelectEnd
+            | Command::MoveWordLeft
+            | Command::MoveWordRight
+            | Command::Indent
+            | Command::Dedent
+            | Command::InsertNewline
+            | Command::DeleteBackward
+            | Command::AddCursorAbove
+            | Command::AddCursorBelow
+            | Command::AddNextOccurrence
+    )
+}
+
+// Commands that work on a block selection or don't move the cursor.
+fn keeps_block(command: Command) -> bool {
+    matches!(
+        command,
+        Command::Save
+            | Command::SaveAs
+            | Command::ShowMessages
+            | Command::DismissMessage
+            | Command::Execute
+            | Command::ReloadConfig
+            | Command::SelectTheme
+            | Command::SelectSyntax
+            | Command::DeleteBackward
+            | Command::DeleteForward
+            | Command::SelectBlockUp
+            | Command::SelectBlockDown
+            | Command::SelectBlockLeft
+            | Command::SelectBlockRight
+            | Command::Copy
+            | Command::Cut
+            | Command::Paste
+    )
+}
+
+fn is_writable(filename: &str) -> bool {
+    match CString::new(filename) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+        Err(_) => false,
+    }
+}
+
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("use kk --<filename>");
+    // `--set key=value` (or `-c`) overrides the configuration files.
+    let mut overrides = Vec::new();
+    let mut filename = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--set" | "-c" => overrides.extend(args.next()),
+            _ => filename = Some(arg),
+        }
     }
-    let filename = &args[1];
+    let Some(filename) = filename else {
+        panic!("use kk [--set key=value] <filename>");
+    };
 
     let path = Path::new(filename.trim());
+    let mut config = Config::load(path, &overrides);
+    let mut term = Terminal::new()?;
     if path.is_dir() {
-        let mut explorer = filemanager::Explorer::new();
+        let mut explorer = filemanager::Explorer::new(&config);
+        for error in config.errors.drain(..) {
+            explorer.messages.error(error);
+        }
         explorer.load_dir(path);
-        if let Some(selected_file) = explorer.run() {
+        if let Some(selected_file) = explorer.run(&mut term)? {
             let name = selected_file.to_string_lossy().to_string();
-            let mut editor = Editor::load_file(&name);
-            editor.run(&name)?;
+            let mut config = explorer.config.reload(&selected_file);
+            let mut editor = Editor::load_file(&name, &config);
+            for error in config.errors.drain(..) {
+                editor.messages.error(error);
+            }
+            editor.run(&mut term)?;
         }
     } else {
-        let mut editor = Editor::load_file(filename);
-        editor.run(filename)?;
+        let mut editor = Editor::load_file(&filename, &config);
+        for error in config.errors.drain(..) {
+            editor.messages.error(error);
+        }
+        editor.run(&mut term)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(text: &[&str]) -> Editor {
+        let config = Config::builtin();
+        let filename = env::temp_dir().join("kk-test.txt");
+        let mut editor = Editor::new(&filename.to_string_lossy(), &config);
+        editor.lines = text.iter().map(|line| line.to_string()).collect();
+        editor
+    }
+
+    #[test]
+    fn the_cursor_stays_on_screen_after_a_resize() {
+        let lines: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+        let mut editor = editor(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        (editor.cursor_y, editor.cursor_x) = (50, 7);
+        for size in [(80, 24), (20, 5), (1, 1), (200, 60)] {
+            editor.resize(size);
+            editor.scroll();
+            let rows = editor.row_offset..editor.row_offset + editor.text_height();
+            assert!(rows.contains(&50), "{:?} at {:?}", rows, size);
+            let mut screen = Vec::new();
+            editor.draw(&mut screen).unwrap();
+        }
+    }
+
+    #[test]
+    fn home_goes_to_the_text_then_the_line_start() {
+        let mut editor = editor(&["    foo"]);
+        editor.cursor_x = 6;
+        editor.move_cursor(Key::Home);
+        assert_eq!(editor.cursor_x, 4);
+        editor.move_cursor(Key::Home);
+        assert_eq!(editor.cursor_x, 0);
+        editor.move_cursor(Key::Home);
+        assert_eq!(editor.cursor_x, 4);
+        editor.move_cursor(Key::End);
+        assert_eq!(editor.cursor_x, 7);
+    }
+
+    #[test]
+    fn vertical_moves_keep_the_column_across_short_lines() {
+        let mut editor = editor(&["abcdef", "ab", "abcdef"]);
+        editor.cursor_x = 5;
+        editor.move_cursor(Key::Down);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 2));
+        editor.move_cursor(Key::Down);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (2, 5));
+        editor.move_cursor(Key::Left);
+        editor.move_cursor(Key::Up);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (1, 2));
+        editor.move_cursor(Key::Up);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 4));
+    }
+
+    #[test]
+    fn pages_stop_at_the_ends_of_the_file() {
+        let lines: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let mut editor = editor(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        let page = editor.text_height();
+        editor.move_cursor(Key::PageDown);
+        assert_eq!((editor.cursor_y, editor.row_offset), (page, page));
+        editor.move_cursor(Key::CtrlEnd);
+        editor.move_cursor(Key::PageDown);
+        assert_eq!(editor.cursor_y, 99);
+        editor.move_cursor(Key::CtrlHome);
+        editor.move_cursor(Key::PageUp);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 0));
+    }
+
+    // What the main loop does after every command.
+    fn commit(editor: &mut Editor) {
+        let cursor = (editor.cursor_y, editor.cursor_x);
+        editor.history.commit(&editor.lines, cursor);
+    }
+
+    fn cursors(editor: &Editor) -> Vec<(usize, usize)> {
+        let mut all: Vec<_> = editor
+            .cursors
+            .iter()
+            .map(|cursor| (cursor.y, cursor.x))
+            .collect();
+        all.push((editor.cursor_y, editor.cursor_x));
+        all.sort();
+        all
+    }
+
+    #[test]
+    fn cursors_edit_together_and_undo_at_once() {
+        let mut editor = editor(&["a", "b", "c"]);
+        editor.cursor_x = 1;
+        editor.add_cursor(true);
+        editor.add_cursor(true);
+        assert_eq!(cursors(&editor), [(0, 1), (1, 1), (2, 1)]);
+        editor.at_cursors(|editor| editor.type_char('x'));
+        commit(&mut editor);
+        editor.at_cursors(|editor| editor.type_char('y'));
+        commit(&mut editor);
+        assert_eq!(editor.lines, ["axy", "bxy", "cxy"]);
+        editor.at_cursors(Editor::delete_char);
+        commit(&mut editor);
+        assert_eq!(editor.lines, ["ax", "bx", "cx"]);
+        editor.undo(false);
+        assert_eq!(editor.lines, ["axy", "bxy", "cxy"]);
+        editor.undo(false);
+        assert_eq!(editor.lines, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn new_lines_move_the_cursors_below() {
+        let mut editor = editor(&["ab", "cd"]);
+        editor.cursor_x = 1;
+        editor.add_cursor(true);
+        editor.at_cursors(Editor::insert_new_line);
+        assert_eq!(editor.lines, ["a", "b", "c", "d"]);
+        assert_eq!(cursors(&editor), [(1, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn next_occurrences_are_selected_in_turn() {
+        let mut editor = editor(&["foo bar", "foo"]);
+        editor.cursor_x = 1;
+        editor.add_next_occurrence();
+        assert_eq!(editor.selection(), Some(((0, 0), (0, 3))));
+        editor.add_next_occurrence();
+        assert_eq!(cursors(&editor), [(0, 3), (1, 3)]);
+        editor.at_cursors(|editor| editor.type_char('z'));
+        assert_eq!(editor.lines, ["z bar", "z"]);
+    }
+
+    #[test]
+    fn overlapping_selections_indent_each_line_once() {
+        let mut editor = editor(&["a", "b", "c"]);
+        editor.anchor = Some((0, 0));
+        (editor.cursor_y, editor.cursor_x) = (1, 1);
+        editor.cursors.push(Cursor {
+            y: 2,
+            x: 1,
+            anchor: Some((1, 0)),
+            goal_col: None,
+        });
+        editor.indent();
+        commit(&mut editor);
+        assert_eq!(editor.lines, ["\ta", "\tb", "\tc"]);
+        editor.undo(false);
+        assert_eq!(editor.lines, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn a_selection_ending_at_a_line_start_leaves_that_line_out() {
+        let cursor = |anchor, (y, x)| Cursor {
+            y,
+            x,
+            anchor: Some(anchor),
+            goal_col: None,
+        };
+        assert_eq!(cursor((2, 0), (0, 3)).rows(), 0..2);
+        assert_eq!(cursor((0, 3), (2, 1)).rows(), 0..3);
+        assert_eq!(cursor((1, 1), (1, 1)).rows(), 1..2);
+    }
+
+    #[test]
+    fn blocks_are_cut_and_pasted_as_columns() {
+        let mut editor = editor(&["abcd", "ef", "ghij"]);
+        editor.set_block(Block {
+            anchor: (0, 1),
+            cursor: (2, 3),
+        });
+        editor.cut();
+        commit(&mut editor);
+        assert_eq!(editor.lines, ["ad", "e", "gj"]);
+        (editor.cursor_y, editor.cursor_x) = (0, 0);
+        editor.block = None;
+        editor.paste();
+        commit(&mut editor);
+        assert_eq!(editor.lines, ["bcad", "f e", "higj"]);
+        editor.undo(false);
+        assert_eq!(editor.lines, ["ad", "e", "gj"]);
+    }
+
+    #[test]
+    fn typing_in_a_block_pads_short_lines() {
+        let mut editor = editor(&["abc", "", "abc"]);
+        editor.set_block(Block {
+            anchor: (0, 2),
+            cursor: (2, 2),
+        });
+        editor.type_in_block(editor.block.unwrap(), '|');
+        assert_eq!(editor.lines, ["ab|c", "  |", "ab|c"]);
+        assert_eq!(editor.block.unwrap().columns(), 3..3);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{self, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::history::Edit;
+
+// Named positions, as in Vim: `a`-`z` belong to one file, `A`-`Z` to the
+// whole editor and take you to their file. All of them are kept in
+// `marks.toml` in the state directory, by absolute path of the file.
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Position {
+    line: usize,
+    col: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GlobalMark {
+    file: String,
+    line: usize,
+    col: usize,
+}
+
+// Lines and columns in the file count from 1.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct Store {
+    global: BTreeMap<String, GlobalMark>,
+    files: BTreeMap<String, BTreeMap<String, Position>>,
+}
+
+fn store_path() -> Option<PathBuf> {
+    config::state_dir().map(|dir| dir.join("marks.toml"))
+}
+
+fn read_store() -> io::Result<Store> {
+    let Some(path) = store_path() else {
+        return Ok(Store::default());
+    };
+    match fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.message().to_string())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Store::default()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_store(store: &Store) -> io::Result<()> {
+    let Some(path) = store_path() else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let content = toml::to_string(store).map_err(io::Error::other)?;
+    fs::write(path, content)
+}
+
+pub fn is_valid(name: char) -> bool {
+    name.is_ascii_alphabetic()
+}
+
+pub fn is_global(name: char) -> bool {
+    name.is_ascii_uppercase()
+}
+
+fn absolute(filename: &str) -> Option<PathBuf> {
+    fs::canonicalize(filename)
+        .or_else(|_| path::absolute(filename))
+        .ok()
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+// A mark set in another file.
+pub struct Elsewhere {
+    pub name: char,
+    pub file: String,
+    pub y: usize,
+    pub x: usize,
+}
+
+// The marks of one file, as (line, char index).
+#[derive(Default)]
+pub struct Marks {
+    path: Option<PathBuf>,
+    marks: BTreeMap<char, (usize, usize)>,
+    // Whether the store may have marks for the file. Files that never had
+    // any leave it alone.
+    stored: bool,
+}
+
+impl Marks {
+    pub fn load(filename: &str) -> io::Result<Self> {
+        let mut marks = Marks {
+            path: absolute(filename),
+            marks: BTreeMap::new(),
+            stored: false,
+        };
+        let Some(file) = marks.path.as_deref().map(key) else {
+            return Ok(marks);
+        };
+        let store = read_store()?;
+        let local = store.files.get(&file).into_iter().flatten();
+        let local = local.map(|(name, position)| (name, position.line, position.col));
+        let global = store.global.iter().filter(|(_, mark)| mark.file == file);
+        let global = global.map(|(name, mark)| (name, mark.line, mark.col));
+        for (name, line, col) in local.chain(global) {
+            if let Some(name) = name.chars().next().filter(|&c| is_valid(c)) {
+                let at = (line.saturating_sub(1), col.saturating_sub(1));
+                marks.marks.insert(name, at);
+            }
+        }
+        marks.stored = !marks.marks.is_empty();
+        Ok(marks)
+    }
+
+    // Writes the marks of this file over what the store had for it.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(file) = self.path.as_deref().map(key) else {
+            return Ok(());
+        };
+        if self.marks.is_empty() && !self.stored {
+            return Ok(());
+        }
+        let mut store = read_store()?;
+        let position = |(y, x): (usize, usize)| Position {
+            line: y + 1,
+            col: x + 1,
+        };
+        let local: BTreeMap<String, Position> = self
+            .marks
+            .iter()
+            .filter(|(name, _)| !is_global(**name))
+            .map(|(name, &at)| (name.to_string(), position(at)))
+            .collect();
+        match local.is_empty() {
+            true => store.files.remove(&file),
+            false => store.files.insert(file.clone(), local),
+        };
+        store.global.retain(|_, mark| mark.file != file);
+        for (name, &(y, x)) in self.marks.iter().filter(|(name, _)| is_global(**name)) {
+            let mark = GlobalMark {
+                file: file.clone(),
+                line: y + 1,
+                col: x + 1,
+            };
+            store.global.insert(name.to_string(), mark);
+        }
+        write_store(&store)?;
+        self.stored = !self.marks.is_empty();
+        Ok(())
+    }
+
+    // Moves the marks over to `filename`, as after Save As. The old file
+    // keeps what was saved for it.
+    pub fn rename(&mut self, filename: &str) {
+        self.path = absolute(filename);
+        // The new file may have had marks of its own.
+        self.stored = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    pub fn get(&self, name: char) -> Option<(usize, usize)> {
+        self.marks.get(&name).copied()
+    }
+
+    pub fn set(&mut self, name: char, at: (usize, usize)) {
+        self.marks.insert(name, at);
+    }
+
+    pub fn remove(&mut self, name: char) {
+        self.marks.remove(&name);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (char, (usize, usize))> + '_ {
+        self.marks.iter().map(|(&name, &at)| (name, at))
+    }
+
+    // The first mark on line `y`, for the sign column.
+    pub fn on_line(&self, y: usize) -> Option<char> {
+        self.iter()
+            .find(|&(_, (line, _))| line == y)
+            .map(|(name, _)| name)
+    }
+
+    // Keeps the marks on their lines. Marks on replaced lines stay on the
+    // last line of the replacement.
+    pub fn shift(&mut self, edit: Edit) {
+        for (y, _) in self.marks.values_mut() {
+            if *y >= edit.start + edit.old {
+                *y = *y - edit.old + edit.new;
+            } else if *y >= edit.start + edit.new {
+                *y = (edit.start + edit.new).saturating_sub(1).max(edit.start);
+            }
+        }
+    }
+
+    // Upper-case marks in other files.
+    pub fn elsewhere(&self) -> io::Result<Vec<Elsewhere>> {
+        let file = self.path.as_deref().map(key);
+        let store = read_store()?;
+        let marks = store
+            .global
+            .into_iter()
+            .filter(|(_, mark)| Some(&mark.file) != file.as_ref())
+            .filter_map(|(name, mark)| {
+                let name = name.chars().next().filter(|&c| is_global(c))?;
+                Some(Elsewhere {
+                    name,
+                    file: mark.file,
+                    y: mark.line.saturating_sub(1),
+                    x: mark.col.saturating_sub(1),
+                })
+            })
+            .collect();
+        Ok(marks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marks(at: &[(char, usize)]) -> Marks {
+        let mut marks = Marks::default();
+        for &(name, y) in at {
+            marks.set(name, (y, 0));
+        }
+        marks
+    }
+
+    fn rows(marks: &Marks) -> Vec<(char, usize)> {
+        marks.iter().map(|(name, (y, _))| (name, y)).collect()
+    }
+
+    #[test]
+    fn marks_after_an_edit_move_with_their_lines() {
+        let mut marks = marks(&[('a', 1), ('b', 5)]);
+        marks.shift(Edit {
+            start: 2,
+            old: 0,
+            new: 3,
+        });
+        assert_eq!(rows(&marks), [('a', 1), ('b', 8)]);
+        marks.shift(Edit {
+            start: 0,
+            old: 2,
+            new: 1,
+        });
+        assert_eq!(rows(&marks), [('a', 0), ('b', 7)]);
+    }
+
+    #[test]
+    fn marks_on_deleted_lines_stay_next_to_them() {
+        let mut marks = marks(&[('a', 3), ('b', 4)]);
+        marks.shift(Edit {
+            start: 2,
+            old: 3,
+            new: 1,
+        });
+        assert_eq!(rows(&marks), [('a', 2), ('b', 2)]);
+        marks.shift(Edit {
+            start: 2,
+            old: 1,
+            new: 0,
+        });
+        assert_eq!(rows(&marks), [('a', 2), ('b', 2)]);
+    }
+
+    #[test]
+    fn upper_case_marks_are_global() {
+        assert!(is_valid('a') && is_valid('Z'));
+        assert!(!is_valid('1') && !is_valid('ä'));
+        assert!(is_global('A') && !is_global('a'));
+        assert_eq!(marks(&[('b', 2), ('a', 2)]).on_line(2), Some('a'));
+    }
+}
@@ -0,0 +1,196 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use termion::event::{Event, Key};
+use termion::{clear, color, cursor, style};
+
+use crate::colors;
+use crate::terminal::{TermEvent, Terminal};
+
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(4);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+}
+
+impl Message {
+    pub fn color(&self) -> String {
+        match self.level {
+            Level::Info => format!("{}", color::Fg(color::Reset)),
+            Level::Warning => format!("{}", colors::Ansi(3)),
+            Level::Error => format!("{}", colors::Ansi(9)),
+        }
+    }
+}
+
+pub struct Messages {
+    history: Vec<Message>,
+    shown_at: Option<Instant>,
+    timeout: Duration,
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        Messages {
+            history: Vec::new(),
+            shown_at: None,
+            timeout: MESSAGE_TIMEOUT,
+        }
+    }
+}
+
+impl Messages {
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(Level::Info, text.into());
+    }
+
+    pub fn warning(&mut self, text: impl Into<String>) {
+        self.push(Level::Warning, text.into());
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.push(Level::Error, text.into());
+    }
+
+    fn push(&mut self, level: Level, text: String) {
+        self.history.push(Message { level, text });
+        self.shown_at = Some(Instant::now());
+    }
+
+    pub fn current(&self) -> Option<&Message> {
+        self.time_left()?;
+        self.history.last()
+    }
+
+    // How long the current message stays on screen, the event loop uses it
+    // to wake up and clear the message line.
+    pub fn time_left(&self) -> Option<Duration> {
+        let elapsed = self.shown_at?.elapsed();
+        self.timeout.checked_sub(elapsed).filter(|d| !d.is_zero())
+    }
+
+    pub fn dismiss(&mut self) {
+        self.shown_at = None;
+    }
+
+    pub fn draw<W: Write>(&self, out: &mut W, row: u16) -> io::Result<()> {
+        write!(out, "{}{}{}", cursor::Goto(1, row), style::Reset, clear::CurrentLine)?;
+        if let Some(message) = self.current() {
+            write!(out, "{}{}{}", message.color(), message.text, style::Reset)?;
+        }
+        Ok(())
+    }
+
+    pub fn show_history(&self, term: &mut Terminal) -> io::Result<()> {
+        let mut offset = usize::MAX;
+
+        loop {
+            let (width, height) = term.size();
+            let visible = (height as usize).saturating_sub(2).max(1);
+            let max_offset = self.history.len().saturating_sub(visible);
+            offset = offset.min(max_offset);
+
+            write!(
+                term,
+                "{}{}{}{}Messages ({}){}",
+                style::Reset,
+                clear::All,
+                cursor::Goto(1, 1),
+                style::Invert,
+                self.history.len(),
+                style::Reset,
+            )?;
+
+            for (i, message) in self.history.iter().skip(offset).take(visible).enumerate() {
+                let text: String = message.text.chars().take(width as usize).collect();
+                write!(
+                    term,
+                    "{}{}{}{}",
+                    cursor::Goto(1, i as u16 + 2),
+                    message.color(),
+                    text,
+                    style::Reset
+                )?;
+            }
+            write!(
+                term,
+                "{}{}Esc/q to close",
+                cursor::Goto(1, height),
+                style::Invert
+            )?;
+            write!(term, "{}{}", style::Reset, cursor::Hide)?;
+            term.flush()?;
+
+            match term.next_event()? {
+                TermEvent::Input(Event::Key(Key::Esc))
+                | TermEvent::Input(Event::Key(Key::Char('q'))) => break,
+                TermEvent::Input(Event::Key(Key::Up)) => offset = offset.saturating_sub(1),
+                TermEvent::Input(Event::Key(Key::Down)) => offset += 1,
+                TermEvent::Input(Event::Key(Key::PageUp)) => {
+                    offset = offset.saturating_sub(visible)
+                }
+                TermEvent::Input(Event::Key(Key::PageDown)) => offset += visible,
+                _ => {}
+            }
+        }
+
+        write!(term, "{}{}{}", style::Reset, clear::All, cursor::Show)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_latest_message_is_shown() {
+        let mut messages = Messages::default();
+        messages.info("Saved");
+        messages.error("Can't write");
+        let current = messages.current().unwrap();
+        assert_eq!(current.level, Level::Error);
+        assert_eq!(current.text, "Can't write");
+    }
+
+    #[test]
+    fn dismissed_messages_stay_in_the_history() {
+        let mut messages = Messages::default();
+        messages.warning("Careful");
+        messages.dismiss();
+        assert!(messages.current().is_none());
+        assert!(messages.time_left().is_none());
+        assert_eq!(messages.history.len(), 1);
+    }
+
+    #[test]
+    fn messages_time_out() {
+        let mut messages = Messages::default();
+        messages.set_timeout(Duration::ZERO);
+        messages.info("Gone");
+        assert!(messages.current().is_none());
+    }
+
+    #[test]
+    fn the_message_line_is_cleared_before_drawing() {
+        let mut messages = Messages::default();
+        messages.info("Saved");
+        let mut out = Vec::new();
+        messages.draw(&mut out, 24).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(&format!("{}", cursor::Goto(1, 24))));
+        assert!(out.contains("Saved"));
+    }
+}
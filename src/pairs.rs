@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+// Pairs typed together unless the `pairs` setting has others for the syntax:
+// each opening character is followed by its closing one.
+pub const DEFAULT: &str = "()[]{}\"\"''";
+
+// The pairs for a syntax, from the per-syntax setting, its `default` entry
+// or the built-in list.
+pub fn for_syntax<'a>(settings: &'a HashMap<String, String>, syntax: &str) -> &'a str {
+    settings
+        .get(&syntax.to_lowercase())
+        .or_else(|| settings.get("default"))
+        .map_or(DEFAULT, |pairs| pairs.as_str())
+}
+
+pub fn is_valid(pairs: &str) -> bool {
+    pairs.chars().count().is_multiple_of(2)
+}
+
+pub fn closer(pairs: &str, c: char) -> Option<char> {
+    let mut chars = pairs.chars();
+    while let (Some(open), Some(close)) = (chars.next(), chars.next()) {
+        if open == c {
+            return Some(close);
+        }
+    }
+    None
+}
+
+pub fn is_closer(pairs: &str, c: char) -> bool {
+    pairs.chars().skip(1).step_by(2).any(|close| close == c)
+}
+
+// Whether typing `open` between `before` and `after` on a line should add
+// `close` as well.
+pub fn should_pair(syntax: &str, open: char, close: char, before: &str, after: &str) -> bool {
+    // Right before a word the closer would end up in the wrong place.
+    if after.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return false;
+    }
+    if open != close {
+        return true;
+    }
+    // A quote after a word is an apostrophe, and one after an odd number of
+    // them closes a string.
+    if before.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+        || !before.matches(open).count().is_multiple_of(2)
+    {
+        return false;
+    }
+    match (syntax, open) {
+        ("Rust", '\'') => rust_char_literal(before),
+        _ => true,
+    }
+}
+
+// `'` in Rust is mostly a lifetime or a label (`&'a`, `<'a>`, `'outer:`),
+// only after these it starts a character literal.
+fn rust_char_literal(before: &str) -> bool {
+    let in_generics = before.matches('<').count() > before.matches('>').count();
+    let previous = before.trim_end().chars().last();
+    !in_generics && previous.is_some_and(|c| "([{=,>|".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_syntax_pairs_win_over_the_default() {
+        let mut settings = HashMap::new();
+        assert_eq!(for_syntax(&settings, "Rust"), DEFAULT);
+        settings.insert("default".to_string(), "()".to_string());
+        settings.insert("html".to_string(), "<>".to_string());
+        assert_eq!(for_syntax(&settings, "HTML"), "<>");
+        assert_eq!(for_syntax(&settings, "Rust"), "()");
+    }
+
+    #[test]
+    fn pairs_are_read_two_characters_at_a_time() {
+        assert!(is_valid("«»()"));
+        assert!(!is_valid("(]["));
+        assert_eq!(closer("«»()", '«'), Some('»'));
+        assert_eq!(closer("«»()", '»'), None);
+        assert!(is_closer("«»()", ')'));
+        assert!(!is_closer("«»()", '('));
+    }
+
+    #[test]
+    fn nothing_is_paired_before_a_word() {
+        assert!(should_pair("Rust", '(', ')', "f", ""));
+        assert!(!should_pair("Rust", '(', ')', "", "x"));
+    }
+
+    #[test]
+    fn quotes_after_words_and_inside_strings_are_not_paired() {
+        assert!(should_pair("Python", '"', '"', "x = ", ""));
+        assert!(!should_pair("Python", '\'', '\'', "don", ""));
+        assert!(!should_pair("Python", '"', '"', "x = \"a ", ""));
+    }
+
+    #[test]
+    fn rust_lifetimes_are_not_paired() {
+        assert!(!should_pair("Rust", '\'', '\'', "fn f(x: &", ""));
+        assert!(!should_pair("Rust", '\'', '\'', "struct S<", ""));
+        assert!(should_pair("Rust", '\'', '\'', "let c = ", ""));
+        assert!(should_pair("Rust", '\'', '\'', "f(", ""));
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::{self, Write};
+
+use termion::event::{Event, Key};
+use termion::{clear, cursor, style};
+
+use crate::terminal::{TermEvent, Terminal};
+
+const MAX_ROWS: usize = 10;
+
+// Lets the user choose one of `items`, typing narrows the list down.
+// `redraw` is called with the highlighted item before the list is drawn over
+// the screen, so the caller can preview it. Returns the index of the chosen
+// item, None when cancelled.
+pub fn pick(
+    term: &mut Terminal,
+    label: &str,
+    items: &[String],
+    current: usize,
+    mut redraw: impl FnMut(&mut Terminal, Option<usize>) -> io::Result<()>,
+) -> io::Result<Option<usize>> {
+    let mut filter = String::new();
+    let mut selected = current;
+    let mut offset = 0;
+
+    loop {
+        let query = filter.to_lowercase();
+        let matches: Vec<usize> = (0..items.len())
+            .filter(|&i| items[i].to_lowercase().contains(&query))
+            .collect();
+        selected = selected.min(matches.len().saturating_sub(1));
+
+        let (width, height) = term.size();
+        let rows = matches
+            .len()
+            .min(MAX_ROWS)
+            .min((height as usize).saturating_sub(3));
+        if selected < offset {
+            offset = selected;
+        }
+        if selected >= offset + rows {
+            offset = selected + 1 - rows;
+        }
+
+        redraw(term, matches.get(selected).copied())?;
+        let top = (height as usize).saturating_sub(1 + rows);
+        for row in 0..rows {
+            let i = offset + row;
+            let text: String = items[matches[i]]
+                .chars()
+                .take((width as usize).saturating_sub(2))
+                .collect();
+            write!(
+                term,
+                "{}{}{}",
+                cursor::Goto(1, (top + row) as u16),
+                style::Reset,
+                clear::CurrentLine
+            )?;
+            if i == selected {
+                write!(term, "{}> {}{}", style::Invert, text, style::Reset)?;
+            } else {
+                write!(term, "  {}", text)?;
+            }
+        }
+        write!(
+            term,
+            "{}{}{}{}{} ({}/{}){}",
+            cursor::Goto(1, height),
+            style::Reset,
+            clear::CurrentLine,
+            label,
+            filter,
+            matches.len(),
+            items.len(),
+            cursor::Goto((label.chars().count() + filter.chars().count() + 1) as u16, height),
+        )?;
+        write!(term, "{}", cursor::Show)?;
+        term.flush()?;
+
+        let key = match term.next_event()? {
+            TermEvent::Input(Event::Key(key)) => key,
+            _ => continue,
+        };
+        match key {
+            Key::Char('\n') => return Ok(matches.get(selected).copied()),
+            Key::Esc | Key::Ctrl('c') | Key::Ctrl('g') => return Ok(None),
+            Key::Up | Key::Ctrl('p') => selected = selected.saturating_sub(1),
+            Key::Down | Key::Ctrl('n') => selected += 1,
+            Key::PageUp => selected = selected.saturating_sub(rows.max(1)),
+            Key::PageDown => selected += rows.max(1),
+            Key::Backspace => {
+                filter.pop();
+                selected = 0;
+            }
+            Key::Char(c) if !c.is_control() => {
+                filter.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+
+use termion::event::{Event, Key};
+use termion::{clear, cursor, style};
+
+use crate::terminal::{TermEvent, Terminal};
+
+pub type Completer = Box<dyn Fn(&str) -> Vec<String>>;
+
+// Input history of every prompt, keyed by the prompt name, so that the
+// editor and the file manager share it for the whole session.
+static HISTORY: LazyLock<Mutex<HashMap<&'static str, Vec<String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+const HISTORY_LIMIT: usize = 100;
+
+fn history(name: &str) -> Vec<String> {
+    HISTORY.lock().unwrap().get(name).cloned().unwrap_or_default()
+}
+
+fn remember(name: &'static str, entry: &str) {
+    let mut history = HISTORY.lock().unwrap();
+    let entries = history.entry(name).or_default();
+    entries.retain(|e| e != entry);
+    entries.push(entry.to_string());
+    if entries.len() > HISTORY_LIMIT {
+        entries.remove(0);
+    }
+}
+
+pub fn last_entry(name: &str) -> Option<String> {
+    history(name).pop()
+}
+
+pub struct Prompt {
+    name: &'static str,
+    label: String,
+    input: Vec<char>,
+    cursor: usize,
+    completer: Option<Completer>,
+    completions: Vec<String>,
+    completion_index: Option<usize>,
+}
+
+impl Prompt {
+    pub fn new(name: &'static str, label: impl Into<String>) -> Self {
+        Prompt {
+            name,
+            label: label.into(),
+            input: Vec::new(),
+            cursor: 0,
+            completer: None,
+            completions: Vec::new(),
+            completion_index: None,
+        }
+    }
+
+    pub fn initial(mut self, text: &str) -> Self {
+        self.input = text.chars().collect();
+        self.cursor = self.input.len();
+        self
+    }
+
+    pub fn completer(mut self, completer: Completer) -> Self {
+        self.completer = Some(completer);
+        self
+    }
+
+    // Reads a line on the last row of the screen. `redraw` repaints whatever
+    // is behind the prompt after a resize. Returns `None` when cancelled.
+    pub fn run<F>(mut self, term: &mut Terminal, mut redraw: F) -> io::Result<Option<String>>
+    where
+        F: FnMut(&mut Terminal) -> io::Result<()>,
+    {
+        let history = history(self.name);
+        let mut history_pos = history.len();
+        let mut draft = String::new();
+
+        loop {
+            self.draw(term)?;
+
+            let key = match term.next_event()? {
+                TermEvent::Input(Event::Key(key)) => key,
+                TermEvent::Input(_) => continue,
+                TermEvent::Resize | TermEvent::Highlighted => {
+                    redraw(term)?;
+                    continue;
+                }
+            };
+
+            if key != Key::Char('\t') {
+                self.completions.clear();
+                self.completion_index = None;
+            }
+
+            match key {
+                Key::Char('\n') => {
+                    let input: String = self.input.iter().collect();
+                    if !input.is_empty() {
+                        remember(self.name, &input);
+                    }
+                    return Ok(Some(input));
+                }
+                Key::Esc | Key::Ctrl('c') | Key::Ctrl('g') => return Ok(None),
+                Key::Char('\t') => self.complete(),
+                Key::Char(c) => {
+                    self.input.insert(self.cursor, c);
+                    self.cursor += 1;
+                }
+                Key::Backspace if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.input.remove(self.cursor);
+                }
+                Key::Delete | Key::Ctrl('d') if self.cursor < self.input.len() => {
+                    self.input.remove(self.cursor);
+                }
+                Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => {
+                    self.cursor = (self.cursor + 1).min(self.input.len())
+                }
+                Key::Home | Key::Ctrl('a') => self.cursor = 0,
+                Key::End | Key::Ctrl('e') => self.cursor = self.input.len(),
+                Key::Ctrl('u') => {
+                    self.input.drain(..self.cursor);
+                    self.cursor = 0;
+                }
+                Key::Ctrl('k') => self.input.truncate(self.cursor),
+                Key::Up if history_pos > 0 => {
+                    if history_pos == history.len() {
+                        draft = self.input.iter().collect();
+                    }
+                    history_pos -= 1;
+                    self.set_input(&history[history_pos]);
+                }
+                Key::Down if history_pos < history.len() => {
+                    history_pos += 1;
+                    let text = history.get(history_pos).unwrap_or(&draft).clone();
+                    self.set_input(&text);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn set_input(&mut self, text: &str) {
+        self.input = text.chars().collect();
+        self.cursor = self.input.len();
+    }
+
+    // The first Tab completes the longest common prefix of the candidates,
+    // repeated presses cycle through them.
+    fn complete(&mut self) {
+        let Some(completer) = &self.completer else {
+            return;
+        };
+
+        if let Some(index) = self.completion_index {
+            if self.completions.is_empty() {
+                return;
+            }
+            let next = (index + 1) % self.completions.len();
+            self.completion_index = Some(next);
+            let text = self.completions[next].clone();
+            self.set_input(&text);
+            return;
+        }
+
+        let input: String = self.input.iter().collect();
+        self.completions = completer(&input);
+        self.completion_index = None;
+
+        match self.completions.len() {
+            0 => {}
+            1 => {
+                let text = self.completions[0].clone();
+                self.set_input(&text);
+            }
+            _ => {
+                let prefix = common_prefix(&self.completions);
+                if prefix.chars().count() > self.input.len() {
+                    self.set_input(&prefix);
+                } else {
+                    self.completion_index = Some(self.completions.len() - 1);
+                }
+            }
+        }
+    }
+
+    fn draw(&self, term: &mut Terminal) -> io::Result<()> {
+        let (width, height) = term.size();
+        let width = width as usize;
+        let label_len = self.label.chars().count();
+
+        let room = width.saturating_sub(label_len + 1).max(1);
+        let skip = (self.cursor + 1).saturating_sub(room);
+        let visible: String = self.input.iter().skip(skip).take(room).collect();
+
+        let mut hint = String::new();
+        if self.completions.len() > 1 {
+            hint = format!("  [{}]", self.completions.join(" "));
+        }
+        let used = label_len + visible.chars().count();
+        let hint: String = hint.chars().take(width.saturating_sub(used)).collect();
+
+        write!(
+            term,
+            "{}{}{}{}{}{}{}{}{}",
+            cursor::Goto(1, height),
+            style::Reset,
+            clear::CurrentLine,
+            style::Bold,
+            self.label,
+            style::Reset,
+            visible,
+            hint,
+            cursor::Goto((label_len + self.cursor - skip + 1).min(width) as u16, height),
+        )?;
+        write!(term, "{}", cursor::Show)?;
+        term.flush()
+    }
+}
+
+pub fn confirm<F>(term: &mut Terminal, message: &str, mut redraw: F) -> io::Result<bool>
+where
+    F: FnMut(&mut Terminal) -> io::Result<()>,
+{
+    loop {
+        let (_, height) = term.size();
+        write!(
+            term,
+            "{}{}{}{} (Y/n): {}",
+            cursor::Goto(1, height),
+            clear::CurrentLine,
+            style::Invert,
+            message,
+            style::Reset,
+        )?;
+        term.flush()?;
+
+        match term.next_event()? {
+            TermEvent::Input(Event::Key(Key::Char('y'))) | TermEvent::Input(Event::Key(Key::Char('Y'))) => return Ok(true),
+            TermEvent::Input(Event::Key(Key::Char('n'))) | TermEvent::Input(Event::Key(Key::Char('N'))) => return Ok(false),
+            TermEvent::Resize | TermEvent::Highlighted => redraw(term)?,
+            _ => {}
+        }
+    }
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix: Vec<char> = candidates[0].chars().collect();
+    for candidate in &candidates[1..] {
+        let len = prefix
+            .iter()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(len);
+    }
+    prefix.into_iter().collect()
+}
+
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
+
+pub fn complete_path(input: &str) -> Vec<String> {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(i) => (&input[..=i], &input[i + 1..]),
+        None => ("", input),
+    };
+    let dir_path = if dir.is_empty() { ".".to_string() } else { expand_home(dir) };
+
+    let Ok(read_dir) = fs::read_dir(Path::new(&dir_path)) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<String> = read_dir
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if e.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn input(prompt: &Prompt) -> String {
+        prompt.input.iter().collect()
+    }
+
+    #[test]
+    fn history_keeps_one_copy_of_each_entry() {
+        remember("test-history", "a");
+        remember("test-history", "b");
+        remember("test-history", "a");
+        assert_eq!(history("test-history"), strings(&["b", "a"]));
+        assert_eq!(last_entry("test-history").as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn history_is_limited() {
+        for i in 0..HISTORY_LIMIT + 5 {
+            remember("test-limit", &i.to_string());
+        }
+        let entries = history("test-limit");
+        assert_eq!(entries.len(), HISTORY_LIMIT);
+        assert_eq!(entries[0], "5");
+    }
+
+    #[test]
+    fn tab_completes_the_common_prefix_then_cycles() {
+        let completer: Completer = Box::new(|_| strings(&["select-theme", "select-syntax"]));
+        let mut prompt = Prompt::new("test-complete", "")
+            .initial("se")
+            .completer(completer);
+        prompt.complete();
+        assert_eq!(input(&prompt), "select-");
+        prompt.complete();
+        prompt.complete();
+        assert_eq!(input(&prompt), "select-theme");
+        prompt.complete();
+        assert_eq!(input(&prompt), "select-syntax");
+    }
+
+    #[test]
+    fn a_single_candidate_is_taken() {
+        let completer: Completer = Box::new(|_| strings(&["save-as"]));
+        let mut prompt = Prompt::new("test-single", "")
+            .initial("sa")
+            .completer(completer);
+        prompt.complete();
+        assert_eq!(input(&prompt), "save-as");
+        assert_eq!(prompt.cursor, 7);
+    }
+
+    #[test]
+    fn common_prefix_counts_characters() {
+        assert_eq!(common_prefix(&strings(&["äbc", "äbd"])), "äb");
+        assert_eq!(common_prefix(&strings(&["abc", "xyz"])), "");
+    }
+
+    #[test]
+    fn paths_complete_in_their_directory() {
+        let dir = std::env::temp_dir().join(format!("kk-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("setup.py"), "").unwrap();
+        fs::write(dir.join(".secret"), "").unwrap();
+        let base = format!("{}/", dir.display());
+        let found = complete_path(&format!("{}s", base));
+        assert_eq!(
+            found,
+            [format!("{}setup.py", base), format!("{}src/", base)]
+        );
+        assert_eq!(
+            complete_path(&format!("{}.s", base)),
+            [format!("{}.secret", base)]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+pub const DEFAULT_SPEC: &str = "filename modified readonly | syntax encoding eol position percent";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment {
+    FileName,
+    Modified,
+    ReadOnly,
+    Syntax,
+    Encoding,
+    LineEnding,
+    Position,
+    Percent,
+}
+
+impl Segment {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "filename" => Segment::FileName,
+            "modified" => Segment::Modified,
+            "readonly" => Segment::ReadOnly,
+            "syntax" => Segment::Syntax,
+            "encoding" => Segment::Encoding,
+            "eol" => Segment::LineEnding,
+            "position" => Segment::Position,
+            "percent" => Segment::Percent,
+            _ => return None,
+        })
+    }
+}
+
+pub struct StatusInfo<'a> {
+    pub filename: &'a str,
+    pub modified: bool,
+    pub read_only: bool,
+    pub syntax: &'a str,
+    pub encoding: &'a str,
+    pub line_ending: &'a str,
+    pub line: usize,
+    pub col: usize,
+    pub total_lines: usize,
+}
+
+pub struct StatusLine {
+    left: Vec<Segment>,
+    right: Vec<Segment>,
+}
+
+impl Default for StatusLine {
+    fn default() -> Self {
+        StatusLine::parse(DEFAULT_SPEC).unwrap()
+    }
+}
+
+impl StatusLine {
+    // Segments are separated by spaces, a `|` splits the left and the right
+    // aligned groups: "filename modified | position percent".
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (left, right) = spec.split_once('|').unwrap_or((spec, ""));
+        let parse_group = |group: &str| {
+            group
+                .split_whitespace()
+                .map(|name| {
+                    Segment::from_name(name)
+                        .ok_or_else(|| format!("unknown status line segment `{}`", name))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(StatusLine {
+            left: parse_group(left)?,
+            right: parse_group(right)?,
+        })
+    }
+
+    pub fn render(&self, info: &StatusInfo, width: usize) -> String {
+        let left = format!(" {}", join(&self.left, info, " "));
+        let right = format!("{} ", join(&self.right, info, " | "));
+
+        let left_len = left.chars().count();
+        let right_len = right.chars().count();
+        let mut line = if left_len + right_len + 1 < width {
+            format!("{}{}{}", left, " ".repeat(width - left_len - right_len), right)
+        } else {
+            format!("{} {}", left, right)
+        };
+
+        if let Some((i, _)) = line.char_indices().nth(width) {
+            line.truncate(i);
+        }
+        let len = line.chars().count();
+        line.push_str(&" ".repeat(width.saturating_sub(len)));
+        line
+    }
+}
+
+fn join(segments: &[Segment], info: &StatusInfo, separator: &str) -> String {
+    segments
+        .iter()
+        .filter_map(|segment| render_segment(*segment, info))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn render_segment(segment: Segment, info: &StatusInfo) -> Option<String> {
+    match segment {
+        Segment::FileName => Some(info.filename.to_string()),
+        Segment::Modified => info.modified.then(|| "[modified]".to_string()),
+        Segment::ReadOnly => info.read_only.then(|| "[RO]".to_string()),
+        Segment::Syntax => Some(info.syntax.to_string()),
+        Segment::Encoding => Some(info.encoding.to_string()),
+        Segment::LineEnding => Some(info.line_ending.to_string()),
+        Segment::Position => Some(format!(
+            "Line {}/{} Col {}",
+            info.line, info.total_lines, info.col
+        )),
+        Segment::Percent => Some(format!("{}%", info.line * 100 / info.total_lines.max(1))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(modified: bool) -> StatusInfo<'static> {
+        StatusInfo {
+            filename: "main.rs",
+            modified,
+            read_only: false,
+            syntax: "Rust",
+            encoding: "utf-8",
+            line_ending: "LF",
+            line: 5,
+            col: 3,
+            total_lines: 10,
+        }
+    }
+
+    #[test]
+    fn groups_are_aligned_to_both_edges() {
+        let status = StatusLine::parse("filename modified | syntax percent").unwrap();
+        let line = status.render(&info(true), 40);
+        assert_eq!(line, " main.rs [modified]          Rust | 50% ");
+        assert_eq!(line.chars().count(), 40);
+    }
+
+    #[test]
+    fn empty_segments_are_left_out() {
+        let status = StatusLine::parse("filename modified readonly | position").unwrap();
+        let line = status.render(&info(false), 30);
+        assert_eq!(line, " main.rs      Line 5/10 Col 3 ");
+    }
+
+    #[test]
+    fn narrow_lines_are_cut() {
+        let line = StatusLine::default().render(&info(false), 10);
+        assert_eq!(line, " main.rs R");
+    }
+
+    #[test]
+    fn unknown_segments_are_an_error() {
+        let err = StatusLine::parse("filename clock").err().unwrap();
+        assert_eq!(err, "unknown status line segment `clock`");
+    }
+}
@@ -0,0 +1,223 @@
+use std::io::{self, Stdout, Write, stdout};
+use std::panic;
+use std::process;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGWINCH};
+use signal_hook::iterator::Signals;
+use termion::event::Event;
+use termion::input::TermRead;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{ToAlternateScreen, ToMainScreen};
+use termion::{cursor, style};
+
+const ENTER_MOUSE: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1015h\x1b[?1006h";
+const EXIT_MOUSE: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+// The thread that highlights in the background.
+pub const HIGHLIGHT_THREAD: &str = "highlight";
+
+static ORIGINAL_MODE: OnceLock<libc::termios> = OnceLock::new();
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub enum TermEvent {
+    Input(Event),
+    Resize,
+    // The background highlighter has new results.
+    Highlighted,
+}
+
+pub struct Terminal {
+    out: RawTerminal<Stdout>,
+    events: Receiver<io::Result<TermEvent>>,
+    sender: Sender<io::Result<TermEvent>>,
+    width: u16,
+    height: u16,
+}
+
+impl Terminal {
+    pub fn new() -> io::Result<Self> {
+        save_mode();
+        install_panic_hook();
+
+        let mut out = stdout().into_raw_mode()?;
+        write!(out, "{}{}", ToAlternateScreen, ENTER_MOUSE)?;
+        out.flush()?;
+        ACTIVE.store(true, Ordering::SeqCst);
+
+        let (tx, events) = mpsc::channel();
+        spawn_input(tx.clone());
+        spawn_signals(tx.clone())?;
+
+        let (width, height) = termion::terminal_size()?;
+        Ok(Terminal {
+            out,
+            events,
+            sender: tx,
+            width,
+            height,
+        })
+    }
+
+    // Lets other threads post events to the loop.
+    pub fn sender(&self) -> Sender<io::Result<TermEvent>> {
+        self.sender.clone()
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    pub fn next_event(&mut self) -> io::Result<TermEvent> {
+        let evt = self
+            .events
+            .recv()
+            .unwrap_or_else(|_| Err(io::ErrorKind::UnexpectedEof.into()));
+        self.handle(evt)
+    }
+
+    // Like `next_event`, but gives up after `timeout` and returns `None`.
+    pub fn poll_event(&mut self, timeout: Option<Duration>) -> io::Result<Option<TermEvent>> {
+        let Some(timeout) = timeout else {
+            return self.next_event().map(Some);
+        };
+        match self.events.recv_timeout(timeout) {
+            Ok(evt) => self.handle(evt).map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn handle(&mut self, evt: io::Result<TermEvent>) -> io::Result<TermEvent> {
+        let evt = evt?;
+        if let TermEvent::Resize = evt {
+            let (width, height) = termion::terminal_size()?;
+            self.width = width;
+            self.height = height;
+        }
+        Ok(evt)
+    }
+}
+
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+// Puts the terminal back the way we found it: leaves the alternate screen,
+// turns mouse reporting off, shows the cursor and resets the tty mode.
+// Safe to call more than once, only the first call does anything.
+pub fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    let mut out = stdout();
+    let _ = write!(
+        out,
+        "{}{}{}{}",
+        EXIT_MOUSE,
+        style::Reset,
+        cursor::Show,
+        ToMainScreen
+    );
+    let _ = out.flush();
+
+    if let Some(mode) = ORIGINAL_MODE.get() {
+        unsafe {
+            libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, mode);
+        }
+    }
+}
+
+fn save_mode() {
+    let mut mode = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(libc::STDOUT_FILENO, &mut mode) } == 0 {
+        let _ = ORIGINAL_MODE.set(mode);
+    }
+}
+
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // The highlighter reports its panics on the message line, the editor
+        // goes on.
+        if thread::current().name() == Some(HIGHLIGHT_THREAD) {
+            return;
+        }
+        restore();
+        default_hook(info);
+    }));
+}
+
+fn spawn_input(tx: Sender<io::Result<TermEvent>>) {
+    thread::spawn(move || {
+        for evt in io::stdin().events() {
+            let failed = evt.is_err();
+            if tx.send(evt.map(TermEvent::Input)).is_err() || failed {
+                break;
+            }
+        }
+    });
+}
+
+fn spawn_signals(tx: Sender<io::Result<TermEvent>>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGWINCH, SIGTERM, SIGHUP, SIGINT, SIGQUIT])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal != SIGWINCH {
+                restore();
+                process::exit(128 + signal);
+            }
+            if tx.send(Ok(TermEvent::Resize)).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+// termion gives up on mouse events with modifiers. Returns the cell of an
+// Alt+click or an Alt+drag, and whether it is a drag.
+pub fn alt_mouse(bytes: &[u8]) -> Option<(bool, u16, u16)> {
+    let body = std::str::from_utf8(bytes.strip_prefix(b"\x1b[<")?).ok()?;
+    let numbers: Vec<u16> = body
+        .strip_suffix('M')?
+        .split(';')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [8, x, y] => Some((false, x, y)),
+        [40, x, y] => Some((true, x, y)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_clicks_and_drags_are_read_from_sgr_reports() {
+        assert_eq!(alt_mouse(b"\x1b[<8;12;3M"), Some((false, 12, 3)));
+        assert_eq!(alt_mouse(b"\x1b[<40;1;2M"), Some((true, 1, 2)));
+        assert_eq!(alt_mouse(b"\x1b[<0;12;3M"), None);
+        assert_eq!(alt_mouse(b"\x1b[<8;12;3m"), None);
+        assert_eq!(alt_mouse(b"\x1b[<8;12M"), None);
+    }
+}
@@ -0,0 +1,150 @@
+use unicode_width::UnicodeWidthChar;
+
+// Screen columns taken by `c` when it starts at display column `col`.
+pub fn char_width(c: char, col: usize, tab_width: usize) -> usize {
+    if c == '\t' {
+        tab_width - col % tab_width
+    } else {
+        c.width().unwrap_or(0)
+    }
+}
+
+// Display column at which the character with index `idx` starts.
+pub fn display_col(line: &str, idx: usize, tab_width: usize) -> usize {
+    line.chars()
+        .take(idx)
+        .fold(0, |col, c| col + char_width(c, col, tab_width))
+}
+
+// Byte offset of the character with index `idx`, or the line length.
+pub fn byte_index(line: &str, idx: usize) -> usize {
+    line.char_indices().nth(idx).map_or(line.len(), |(i, _)| i)
+}
+
+// Index of the character under display column `col`, or the line length
+// when the column is past the end of the line.
+pub fn char_at_col(line: &str, col: usize, tab_width: usize) -> usize {
+    let mut current = 0;
+    for (i, c) in line.chars().enumerate() {
+        let width = char_width(c, current, tab_width);
+        if col < current + width.max(1) {
+            return i;
+        }
+        current += width;
+    }
+    line.chars().count()
+}
+
+// Letters and digits of any script make words, so do underscores.
+fn class(c: char) -> u8 {
+    match c {
+        c if c.is_whitespace() => 0,
+        c if c.is_alphanumeric() || c == '_' => 1,
+        _ => 2,
+    }
+}
+
+// Index just past the next word after character `idx`: spaces are
+// skipped, then a run of word characters or of punctuation.
+pub fn next_word(line: &str, idx: usize) -> usize {
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = idx;
+    while i < chars.len() && class(chars[i]) == 0 {
+        i += 1;
+    }
+    if let Some(&c) = chars.get(i) {
+        while i < chars.len() && class(chars[i]) == class(c) {
+            i += 1;
+        }
+    }
+    i
+}
+
+// Index of the start of the word before character `idx`.
+pub fn prev_word(line: &str, idx: usize) -> usize {
+    let chars: Vec<char> = line.chars().take(idx).collect();
+    let mut i = chars.len();
+    while i > 0 && class(chars[i - 1]) == 0 {
+        i -= 1;
+    }
+    if i > 0 {
+        let kind = class(chars[i - 1]);
+        while i > 0 && class(chars[i - 1]) == kind {
+            i -= 1;
+        }
+    }
+    i
+}
+
+// Start and end of the word at character `idx` or just before it, both
+// `idx` when there is none.
+pub fn word_at(line: &str, idx: usize) -> (usize, usize) {
+    let chars: Vec<char> = line.chars().collect();
+    let is_word = |i: usize| chars.get(i).is_some_and(|&c| class(c) == 1);
+    let at = match idx {
+        _ if is_word(idx) => idx,
+        1.. if is_word(idx - 1) => idx - 1,
+        _ => return (idx, idx),
+    };
+    let mut start = at;
+    while start > 0 && is_word(start - 1) {
+        start -= 1;
+    }
+    let mut end = at;
+    while is_word(end) {
+        end += 1;
+    }
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tabs_reach_the_next_tab_stop() {
+        assert_eq!(char_width('\t', 0, 4), 4);
+        assert_eq!(char_width('\t', 5, 4), 3);
+        assert_eq!(display_col("a\tb", 2, 4), 4);
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(display_col("日本語", 2, 4), 4);
+        assert_eq!(char_at_col("日本語", 3, 4), 1);
+        assert_eq!(char_at_col("日本語", 10, 4), 3);
+    }
+
+    #[test]
+    fn byte_index_counts_characters() {
+        assert_eq!(byte_index("äb", 1), 2);
+        assert_eq!(byte_index("äb", 5), 3);
+    }
+
+    #[test]
+    fn words_are_runs_of_one_class() {
+        let line = "  foo_1.bar()";
+        assert_eq!(next_word(line, 0), 7);
+        assert_eq!(next_word(line, 7), 8);
+        assert_eq!(next_word(line, 11), 13);
+        assert_eq!(next_word(line, 13), 13);
+        assert_eq!(prev_word(line, 13), 11);
+        assert_eq!(prev_word(line, 7), 2);
+        assert_eq!(prev_word(line, 2), 0);
+    }
+
+    #[test]
+    fn letters_of_any_script_make_words() {
+        assert_eq!(next_word("слово, ещё", 0), 5);
+        assert_eq!(prev_word("слово, ещё", 10), 7);
+    }
+
+    #[test]
+    fn the_word_at_the_cursor_may_end_there() {
+        assert_eq!(word_at("foo.bar", 1), (0, 3));
+        assert_eq!(word_at("foo.bar", 3), (0, 3));
+        assert_eq!(word_at("foo.bar", 4), (4, 7));
+        assert_eq!(word_at("a  b", 2), (2, 2));
+        assert_eq!(word_at("", 0), (0, 0));
+    }
+}
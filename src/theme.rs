@@ -0,0 +1,174 @@
+use std::fs;
+use std::path::PathBuf;
+
+use syntect::highlighting::{self, Color, Theme, ThemeSet};
+use syntect::parsing::Scope;
+use termion::color::Rgb;
+use two_face::theme::EmbeddedLazyThemeSet;
+
+use crate::config;
+
+pub const DEFAULT_THEME: &str = "Dracula";
+
+// User themes are `<config dir>/themes/<name>.tmTheme`.
+fn user_themes() -> Vec<(String, PathBuf)> {
+    let Some(dir) = config::config_dir().map(|dir| dir.join("themes")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut themes: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "tmTheme"))
+        .filter_map(|path| Some((path.file_stem()?.to_string_lossy().to_string(), path)))
+        .collect();
+    themes.sort();
+    themes
+}
+
+// Bundled themes followed by the user ones.
+pub fn theme_names() -> Vec<String> {
+    EmbeddedLazyThemeSet::theme_names()
+        .iter()
+        .map(|theme| theme.as_name().to_string())
+        .chain(user_themes().into_iter().map(|(name, _)| name))
+        .collect()
+}
+
+// Looks up a user theme or one of the bundled ones by its name, e.g.
+// "Nord" or "Solarized (dark)", in any case. User themes shadow bundled
+// ones.
+pub fn load(name: &str) -> Result<Theme, String> {
+    if let Some((_, path)) = user_themes()
+        .into_iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+    {
+        return ThemeSet::get_theme(&path).map_err(|err| format!("{}: {}", path.display(), err));
+    }
+    EmbeddedLazyThemeSet::theme_names()
+        .iter()
+        .find(|theme| theme.as_name().eq_ignore_ascii_case(name))
+        .map(|theme| two_face::theme::extra().get(*theme).clone())
+        .ok_or_else(|| format!("`{}` is not a known theme", name))
+}
+
+pub fn load_or_default(name: &str) -> Theme {
+    load(name).unwrap_or_else(|_| load(DEFAULT_THEME).unwrap())
+}
+
+// Colours of everything that is not highlighted text, taken from the
+// theme's global settings.
+#[derive(Clone, Copy)]
+pub struct Palette {
+    pub background: Rgb,
+    pub foreground: Rgb,
+    pub accent: Rgb,
+    pub gutter: Rgb,
+    pub gutter_foreground: Rgb,
+    pub selection: Rgb,
+    // Matching brackets, the text keeps its colour unless the theme says
+    // otherwise.
+    pub brackets_background: Rgb,
+    pub brackets_foreground: Option<Rgb>,
+    pub status_background: Rgb,
+    pub status_foreground: Rgb,
+}
+
+fn rgb(color: Color) -> Rgb {
+    Rgb(color.r, color.g, color.b)
+}
+
+// Theme colours may be translucent.
+fn over(color: Color, background: Rgb) -> Rgb {
+    blend(rgb(color), background, color.a as f32 / 255.0)
+}
+
+// `amount` of `a` over `b`.
+fn blend(a: Rgb, b: Rgb, amount: f32) -> Rgb {
+    let mix = |x: u8, y: u8| (x as f32 * amount + y as f32 * (1.0 - amount)).round() as u8;
+    Rgb(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+impl Palette {
+    pub fn new(theme: &Theme, background: Option<(u8, u8, u8)>) -> Self {
+        let settings = &theme.settings;
+        let background = match background {
+            Some((r, g, b)) => Rgb(r, g, b),
+            None => settings.background.map(rgb).unwrap_or(Rgb(40, 42, 54)),
+        };
+        let foreground = settings.foreground.map(rgb).unwrap_or(Rgb(248, 248, 242));
+
+        // Most themes don't set an accent, the keyword colour is the most
+        // recognisable colour of a theme.
+        let keyword = Scope::new("keyword").unwrap();
+        let accent = settings.accent.map(rgb).unwrap_or_else(|| {
+            let style = highlighting::Highlighter::new(theme).style_for_stack(&[keyword]);
+            rgb(style.foreground)
+        });
+
+        let selection = settings
+            .selection
+            .map(|c| over(c, background))
+            .unwrap_or(blend(foreground, background, 0.25));
+        Palette {
+            background,
+            foreground,
+            accent,
+            gutter: settings
+                .gutter
+                .map(|c| over(c, background))
+                .unwrap_or(background),
+            gutter_foreground: settings
+                .gutter_foreground
+                .map(|c| over(c, background))
+                .unwrap_or(blend(foreground, background, 0.4)),
+            selection,
+            brackets_background: settings
+                .brackets_background
+                .map(|c| over(c, background))
+                .unwrap_or(selection),
+            brackets_foreground: settings.brackets_foreground.map(|c| over(c, background)),
+            status_background: accent,
+            status_foreground: background,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_themes_are_found_in_any_case() {
+        assert_eq!(
+            load("dracula").unwrap().name,
+            load(DEFAULT_THEME).unwrap().name
+        );
+        assert!(load("no such theme").is_err());
+    }
+
+    #[test]
+    fn the_background_can_be_overridden() {
+        let theme = load(DEFAULT_THEME).unwrap();
+        let palette = Palette::new(&theme, Some((1, 2, 3)));
+        assert_eq!(palette.background, Rgb(1, 2, 3));
+        assert_eq!(palette.status_foreground, Rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn blending_mixes_the_channels() {
+        assert_eq!(
+            blend(Rgb(200, 0, 100), Rgb(0, 200, 100), 0.25),
+            Rgb(50, 150, 100)
+        );
+        let translucent = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 0,
+        };
+        assert_eq!(over(translucent, Rgb(10, 20, 30)), Rgb(10, 20, 30));
+    }
+}
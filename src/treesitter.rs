@@ -0,0 +1,474 @@
+use std::iter;
+use std::ops::Range;
+
+use syntect::highlighting::{self, Style, Theme};
+use syntect::parsing::Scope;
+use tree_sitter::{
+    InputEdit, Language, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree,
+};
+use tree_sitter_language::LanguageFn;
+use tree_sitter_md::{MarkdownParser, MarkdownTree};
+
+use crate::highlight::{Backend, Kind, Span};
+
+// Lines are highlighted this many at a time, one query run each.
+const BLOCK_LINES: usize = 200;
+
+struct Grammar {
+    // The syntect name of the syntax it replaces.
+    name: &'static str,
+    // Base scope for the theme, like syntect's scope of the whole file.
+    scope: &'static str,
+    language: LanguageFn,
+    highlights: &'static str,
+}
+
+const GRAMMARS: [Grammar; 5] = [
+    Grammar {
+        name: "Rust",
+        scope: "source.rust",
+        language: tree_sitter_rust::LANGUAGE,
+        highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+    },
+    Grammar {
+        name: "Python",
+        scope: "source.python",
+        language: tree_sitter_python::LANGUAGE,
+        highlights: tree_sitter_python::HIGHLIGHTS_QUERY,
+    },
+    Grammar {
+        name: "JSON",
+        scope: "source.json",
+        language: tree_sitter_json::LANGUAGE,
+        highlights: tree_sitter_json::HIGHLIGHTS_QUERY,
+    },
+    Grammar {
+        name: "TOML",
+        scope: "source.toml",
+        language: tree_sitter_toml_ng::LANGUAGE,
+        highlights: tree_sitter_toml_ng::HIGHLIGHTS_QUERY,
+    },
+    // Markdown is a block grammar with a separate inline grammar parsed
+    // inside every paragraph, heading and table cell.
+    Grammar {
+        name: "Markdown",
+        scope: "text.html.markdown",
+        language: tree_sitter_md::LANGUAGE,
+        highlights: tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+    },
+];
+
+// Capture names of the bundled queries and the TextMate scopes themes know
+// them by. A capture without an entry falls back to its parent, so
+// `function.method` is coloured as `function`.
+const SCOPES: &[(&str, &str)] = &[
+    ("attribute", "entity.other.attribute-name"),
+    ("boolean", "constant.language"),
+    ("comment", "comment"),
+    ("comment.documentation", "comment.block.documentation"),
+    ("constant", "constant"),
+    ("constant.builtin", "constant.language"),
+    ("constructor", "support.type"),
+    ("embedded", "meta.embedded"),
+    ("escape", "constant.character.escape"),
+    ("function", "entity.name.function"),
+    ("function.builtin", "support.function"),
+    ("function.macro", "entity.name.function.macro"),
+    ("keyword", "keyword"),
+    ("label", "entity.name.label"),
+    ("number", "constant.numeric"),
+    ("operator", "keyword.operator"),
+    ("property", "variable.other.member"),
+    ("punctuation", "punctuation"),
+    ("punctuation.special", "punctuation.definition"),
+    ("string", "string"),
+    ("string.escape", "constant.character.escape"),
+    ("string.special", "constant.other"),
+    ("string.special.key", "support.type.property-name"),
+    ("text.emphasis", "markup.italic"),
+    ("text.literal", "markup.raw.inline"),
+    ("text.reference", "markup.underline.link"),
+    ("text.strong", "markup.bold"),
+    ("text.title", "markup.heading"),
+    ("text.uri", "markup.underline.link"),
+    ("type", "support.type"),
+    ("type.builtin", "storage.type"),
+    ("variable", "variable"),
+    ("variable.builtin", "variable.language"),
+    ("variable.parameter", "variable.parameter"),
+];
+
+fn kind_for(capture: &str) -> Kind {
+    if capture.starts_with("comment") {
+        Kind::Comment
+    } else if capture.starts_with("string") || capture.ends_with("escape") {
+        Kind::String
+    } else {
+        Kind::Code
+    }
+}
+
+fn scope_for(capture: &str) -> Option<&'static str> {
+    let mut name = capture;
+    loop {
+        if let Some((_, scope)) = SCOPES.iter().find(|(n, _)| *n == name) {
+            return Some(scope);
+        }
+        name = &name[..name.rfind('.')?];
+    }
+}
+
+enum Trees {
+    Single(Parser, Option<Tree>),
+    Markdown(MarkdownParser, Option<MarkdownTree>),
+}
+
+// Highlights with a tree-sitter grammar. The tree is kept between edits, so
+// after a change only the edited part of the file is parsed again.
+pub struct TreeSitter {
+    trees: Trees,
+    // The highlight query of the grammar, then the inline one for
+    // Markdown, each with the style and kind of every capture.
+    queries: Vec<(Query, Vec<(Style, Kind)>)>,
+    plain: Style,
+    // Byte offset of the start of every line, and of the end of the text.
+    // Every line counts with a newline after it.
+    offsets: Vec<usize>,
+    parsed: bool,
+}
+
+impl TreeSitter {
+    pub fn supports(syntax: &str) -> bool {
+        GRAMMARS.iter().any(|g| g.name == syntax)
+    }
+
+    // Names of the syntaxes that have a grammar, lower-cased for the
+    // `highlighter` setting.
+    pub fn languages() -> impl Iterator<Item = String> {
+        GRAMMARS.iter().map(|g| g.name.to_lowercase())
+    }
+
+    pub fn new(syntax: &str, theme: &Theme) -> Result<Self, String> {
+        let grammar = GRAMMARS
+            .iter()
+            .find(|g| g.name == syntax)
+            .ok_or_else(|| format!("no tree-sitter grammar for {}", syntax))?;
+
+        let highlighter = highlighting::Highlighter::new(theme);
+        let base = Scope::new(grammar.scope).unwrap();
+        // Scopes end in the language like syntect's do, themes have rules
+        // for `string.quoted.rust` and the like.
+        let language = grammar.scope.rsplit('.').next().unwrap();
+        let style = |capture: &str| {
+            let scope = scope_for(capture)
+                .and_then(|scope| Scope::new(&format!("{}.{}", scope, language)).ok());
+            highlighter.style_for_stack(
+                &[Some(base), scope]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let query = |language: &Language, source: &str| {
+            let query = Query::new(language, source)
+                .map_err(|err| format!("tree-sitter {} query: {}", grammar.name, err))?;
+            let styles = query
+                .capture_names()
+                .iter()
+                .map(|name| (style(name), kind_for(name)))
+                .collect();
+            Ok::<_, String>((query, styles))
+        };
+
+        let language = Language::new(grammar.language);
+        let mut queries = vec![query(&language, grammar.highlights)?];
+        let trees = if grammar.name == "Markdown" {
+            let inline = Language::new(tree_sitter_md::INLINE_LANGUAGE);
+            queries.push(query(&inline, tree_sitter_md::HIGHLIGHT_QUERY_INLINE)?);
+            Trees::Markdown(MarkdownParser::default(), None)
+        } else {
+            let mut parser = Parser::new();
+            parser
+                .set_language(&language)
+                .map_err(|err| format!("tree-sitter {}: {}", grammar.name, err))?;
+            Trees::Single(parser, None)
+        };
+
+        Ok(TreeSitter {
+            trees,
+            queries,
+            plain: style(""),
+            offsets: Vec::new(),
+            parsed: false,
+        })
+    }
+
+    fn parse(&mut self, lines: &[String]) {
+        // The parser reads the text a line at a time instead of having it
+        // joined into one string.
+        let mut read = |_: usize, point: Point| -> &[u8] {
+            match lines.get(point.row) {
+                Some(line) if point.column < line.len() => &line.as_bytes()[point.column..],
+                Some(_) => b"\n",
+                None => b"",
+            }
+        };
+        match &mut self.trees {
+            Trees::Single(parser, tree) => {
+                *tree = parser.parse_with_options(&mut read, tree.as_ref(), None);
+            }
+            Trees::Markdown(parser, tree) => {
+                *tree = parser.parse_with_options(&mut read, tree.as_ref(), Default::default());
+            }
+        }
+        self.parsed = true;
+    }
+
+    // The trees overlapping `bytes`, each with the index of its query.
+    fn trees(&self, bytes: &Range<usize>) -> Vec<(&Tree, usize)> {
+        match &self.trees {
+            Trees::Single(_, tree) => tree.iter().map(|tree| (tree, 0)).collect(),
+            Trees::Markdown(_, tree) => {
+                let Some(tree) = tree else {
+                    return Vec::new();
+                };
+                let inline = tree.inline_trees().iter().filter(|tree| {
+                    let range = tree.root_node().byte_range();
+                    range.start < bytes.end && range.end > bytes.start
+                });
+                iter::once((tree.block_tree(), 0))
+                    .chain(inline.map(|tree| (tree, 1)))
+                    .collect()
+            }
+        }
+    }
+
+    // Spans of lines `rows`. Every byte gets the style of the innermost
+    // capture around it, for the same node the first pattern wins.
+    fn highlight_block(&self, lines: &[String], rows: Range<usize>) -> Vec<Vec<Span>> {
+        let bytes = self.offsets[rows.start]..self.offsets[rows.end];
+        let mut painted: Vec<Option<(Style, Kind)>> = vec![None; bytes.len()];
+        let text = |node: Node| iter::once(node_text(lines, node).into_bytes());
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(bytes.clone());
+        for (tree, index) in self.trees(&bytes) {
+            let (query, styles) = &self.queries[index];
+            let mut found = Vec::new();
+            let mut captures = cursor.captures(query, tree.root_node(), text);
+            while let Some((found_match, capture)) = captures.next() {
+                let capture = found_match.captures[*capture];
+                let range = capture.node.byte_range();
+                found.push((range, found_match.pattern_index, capture.index as usize));
+            }
+            found.sort_by_key(|(range, pattern, _)| {
+                (std::cmp::Reverse(range.len()), std::cmp::Reverse(*pattern))
+            });
+            for (range, _, capture) in found {
+                let start = range.start.max(bytes.start) - bytes.start;
+                let end = range.end.min(bytes.end).saturating_sub(bytes.start);
+                for byte in painted.iter_mut().take(end).skip(start) {
+                    *byte = Some(styles[capture]);
+                }
+            }
+        }
+
+        rows.map(|row| {
+            let start = self.offsets[row] - bytes.start;
+            let mut spans: Vec<Span> = Vec::new();
+            for (i, c) in lines[row].char_indices() {
+                let (style, kind) = painted[start + i].unwrap_or((self.plain, Kind::Code));
+                match spans.last_mut() {
+                    Some((last, last_kind, token)) if *last == style && *last_kind == kind => {
+                        token.push(c)
+                    }
+                    _ => spans.push((style, kind, c.to_string())),
+                }
+            }
+            spans
+        })
+        .collect()
+    }
+}
+
+impl Backend for TreeSitter {
+    fn edit(&mut self, lines: &[String], old: Range<usize>, new: Range<usize>) {
+        self.parsed = false;
+        let tree = match &mut self.trees {
+            Trees::Single(_, tree) => tree.as_mut().map(|tree| tree as &mut dyn Edit),
+            Trees::Markdown(_, tree) => tree.as_mut().map(|tree| tree as &mut dyn Edit),
+        };
+        // Nothing parsed yet, there is only the text to keep up with.
+        let Some(tree) = tree else {
+            self.offsets = line_offsets(lines, 0, 0);
+            return;
+        };
+
+        let before = &self.offsets;
+        let start_byte = before[old.start];
+        let new_end_byte = start_byte
+            + lines[new.clone()]
+                .iter()
+                .map(|l| l.len() + 1)
+                .sum::<usize>();
+        let edit = InputEdit {
+            start_byte,
+            old_end_byte: before[old.end],
+            new_end_byte,
+            start_position: Point::new(old.start, 0),
+            old_end_position: Point::new(old.end, 0),
+            new_end_position: Point::new(new.end, 0),
+        };
+        tree.edit(&edit);
+
+        self.offsets.truncate(old.start);
+        self.offsets
+            .extend(line_offsets(lines, old.start, start_byte));
+    }
+
+    fn highlight(&mut self, lines: &[String], row: usize, emit: &mut dyn FnMut(Vec<Span>) -> bool) {
+        if !self.parsed {
+            self.parse(lines);
+        }
+        let mut start = row;
+        while start < lines.len() {
+            let end = (start + BLOCK_LINES).min(lines.len());
+            for spans in self.highlight_block(lines, start..end) {
+                if !emit(spans) {
+                    return;
+                }
+            }
+            start = end;
+        }
+    }
+}
+
+trait Edit {
+    fn edit(&mut self, edit: &InputEdit);
+}
+
+impl Edit for Tree {
+    fn edit(&mut self, edit: &InputEdit) {
+        Tree::edit(self, edit);
+    }
+}
+
+impl Edit for MarkdownTree {
+    fn edit(&mut self, edit: &InputEdit) {
+        MarkdownTree::edit(self, edit);
+    }
+}
+
+// Offsets of the lines from `row` on, the first one starting at `offset`.
+fn line_offsets(lines: &[String], row: usize, offset: usize) -> Vec<usize> {
+    let mut offsets = vec![offset];
+    let mut offset = offset;
+    for line in &lines[row..] {
+        offset += line.len() + 1;
+        offsets.push(offset);
+    }
+    offsets
+}
+
+// The text of `node`, which may span several lines.
+fn node_text(lines: &[String], node: Node) -> String {
+    let (start, end) = (node.start_position(), node.end_position());
+    let mut text = String::new();
+    for (row, line) in lines.iter().enumerate().take(end.row + 1).skip(start.row) {
+        let line = line.as_bytes();
+        let from = if row == start.row {
+            start.column.min(line.len())
+        } else {
+            0
+        };
+        let to = if row == end.row {
+            end.column.min(line.len())
+        } else {
+            line.len()
+        };
+        text.push_str(&String::from_utf8_lossy(&line[from..to.max(from)]));
+        if row != end.row {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    // A new backend is handed the whole text as one edit, as the worker
+    // does.
+    fn load(syntax: &str, lines: &[String]) -> TreeSitter {
+        let mut backend = TreeSitter::new(syntax, &Theme::default()).unwrap();
+        backend.edit(lines, 0..0, 0..lines.len());
+        backend
+    }
+
+    fn highlight(backend: &mut TreeSitter, lines: &[String]) -> Vec<Vec<Span>> {
+        let mut all = Vec::new();
+        backend.highlight(lines, 0, &mut |spans| {
+            all.push(spans);
+            true
+        });
+        all
+    }
+
+    fn kinds(spans: &[Span]) -> Vec<(Kind, &str)> {
+        spans
+            .iter()
+            .map(|(_, kind, text)| (*kind, text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn captures_fall_back_to_their_parent() {
+        assert_eq!(scope_for("function.method"), Some("entity.name.function"));
+        assert_eq!(
+            scope_for("string.special.key"),
+            Some("support.type.property-name")
+        );
+        assert_eq!(scope_for("nothing.known"), None);
+        assert_eq!(kind_for("comment.documentation"), Kind::Comment);
+        assert_eq!(kind_for("string.escape"), Kind::String);
+        assert_eq!(kind_for("keyword"), Kind::Code);
+    }
+
+    #[test]
+    fn strings_and_comments_are_told_apart() {
+        let text = lines(&["let s = \"a\"; // b"]);
+        let mut backend = load("Rust", &text);
+        let spans = highlight(&mut backend, &text);
+        let kinds = kinds(&spans[0]);
+        assert!(kinds.contains(&(Kind::String, "\"a\"")));
+        assert_eq!(kinds.last(), Some(&(Kind::Comment, "// b")));
+        let joined: String = kinds.iter().map(|(_, text)| *text).collect();
+        assert_eq!(joined, text[0]);
+    }
+
+    #[test]
+    fn edits_give_the_same_spans_as_a_fresh_parse() {
+        let mut text = lines(&["x = 1", "y = 2", "z = 3"]);
+        let mut backend = load("Python", &text);
+        highlight(&mut backend, &text);
+        text.splice(1..2, lines(&["s = '''", "'''"]));
+        backend.edit(&text, 1..2, 1..3);
+        let edited = highlight(&mut backend, &text);
+
+        let mut fresh = load("Python", &text);
+        assert_eq!(edited, highlight(&mut fresh, &text));
+        assert_eq!(kinds(&edited[2])[0].0, Kind::String);
+    }
+
+    #[test]
+    fn only_bundled_grammars_are_supported() {
+        assert!(TreeSitter::supports("Markdown"));
+        assert!(!TreeSitter::supports("markdown"));
+        assert!(TreeSitter::new("Go", &Theme::default()).is_err());
+    }
+}